@@ -0,0 +1,94 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::time::{sleep, Duration};
+use futures::StreamExt;
+use crate::events::{self, LogEvent};
+use crate::{Servers, Events, Logs};
+
+// How many events are kept around for clients that reconnect with a Last-Event-ID
+const BUFFER: usize = 1000;
+
+/// An event from one of the servers, numbered so clients can resume where they left off
+#[derive(Serialize, Debug, Clone)]
+pub struct Envelope {
+    pub id: u64,
+    pub server: String,
+    #[serde(flatten)]
+    pub event: LogEvent,
+}
+
+/// Hands every event from every server out to whoever is listening
+pub struct EventBus {
+    tx: broadcast::Sender<Envelope>,
+    buffer: Mutex<Buffer>,
+}
+
+struct Buffer {
+    next_id: u64,
+    events: VecDeque<Envelope>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        let (tx, _) = broadcast::channel(BUFFER);
+        EventBus {
+            tx,
+            buffer: Mutex::new(Buffer { next_id: 1, events: VecDeque::with_capacity(BUFFER) }),
+        }
+    }
+
+    pub fn publish(&self, server: &str, event: LogEvent) {
+        let mut buffer = self.buffer.lock().unwrap();
+        let envelope = Envelope { id: buffer.next_id, server: server.to_string(), event };
+        buffer.next_id += 1;
+
+        if buffer.events.len() == BUFFER {
+            buffer.events.pop_front();
+        }
+        buffer.events.push_back(envelope.clone());
+
+        // Sent while holding the lock so subscribers never see events out of order
+        // or miss one between the replay and the live stream
+        let _ = self.tx.send(envelope);
+    }
+
+    /// Subscribes to new events, along with the buffered ones that came after `last_id`
+    pub fn subscribe(&self, last_id: Option<u64>) -> (Vec<Envelope>, broadcast::Receiver<Envelope>) {
+        let buffer = self.buffer.lock().unwrap();
+        let missed = match last_id {
+            Some(id) => buffer.events.iter().filter(|e| e.id > id).cloned().collect(),
+            None => Vec::new(),
+        };
+        (missed, self.tx.subscribe())
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> EventBus {
+        EventBus::new()
+    }
+}
+
+/// Parses the output of a server for as long as it's registered, publishing every event
+pub async fn pump(name: String, servers: Servers, events: Events, logs: Logs) {
+    loop {
+        if !servers.read().await.contains_key(&name) {
+            println!("{name} is no longer registered, no longer watching its events");
+            return;
+        }
+
+        // Only ends when the log feed of the server goes away, following it is up to
+        // logs::follow so this never opens one itself
+        if let Some(lines) = logs.live(&name) {
+            let mut lines = Box::pin(lines);
+            while let Some(line) = lines.next().await {
+                if let Some(event) = events::parse(&line) {
+                    events.publish(&name, event);
+                }
+            }
+        }
+        sleep(Duration::from_secs(5)).await;
+    }
+}
@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use crate::Config;
+
+/// The events a chat bridge gets to see
+pub const CHAT_EVENTS: &[&str] = &["chat", "join", "leave", "death", "advancement"];
+
+// Anything longer won't fit in an RCON packet once it's wrapped in the tellraw json
+const MAX_MESSAGE: usize = 256;
+
+/// How messages from somewhere outside the game are shown in chat
+#[derive(Deserialize, Debug, Clone)]
+pub struct ChatSource {
+    pub prefix: String,
+    #[serde(default = "default_colour")]
+    pub colour: String,
+}
+
+fn default_colour() -> String {
+    "gray".to_string()
+}
+
+/// A message to post in game, `source` picks the prefix and colour from the config
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChatMessage {
+    pub source: String,
+    pub sender: Option<String>,
+    pub message: String,
+}
+
+impl ChatMessage {
+    /// Builds the tellraw command that shows this message to everyone on the server
+    pub fn tellraw(&self, config: &Config) -> Vec<String> {
+        let source = config.chat.get(&self.source).cloned().unwrap_or(ChatSource {
+            prefix: format!("[{}]", self.source),
+            colour: default_colour(),
+        });
+
+        let mut text = vec![json!({ "text": format!("{} ", clean(&source.prefix)), "color": source.colour })];
+        if let Some(sender) = &self.sender {
+            text.push(json!({ "text": format!("<{}> ", clean(sender)), "color": "white" }));
+        }
+        let message: String = clean(&self.message).chars().take(MAX_MESSAGE).collect();
+        text.push(json!({ "text": message, "color": "white" }));
+
+        // serde_json takes care of escaping whatever the message contains
+        vec!["tellraw".to_string(), "@a".to_string(), serde_json::Value::Array(text).to_string()]
+    }
+}
+
+/// Drops formatting codes and control characters so outside messages can't style themselves
+fn clean(text: &str) -> String {
+    text.chars().filter(|c| *c != '§' && !c.is_control()).collect()
+}
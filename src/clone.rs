@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use serde::Deserialize;
+use crate::Config;
+use crate::error::Error;
+use crate::server::Server;
+use crate::worlds;
+
+// Cloned servers get a port from here up, away from the usual 25565
+const FIRST_PORT: u16 = 31000;
+// The one file in mc-docker/ that a clone keeps
+const MANIFEST: &str = "modrinth.json";
+
+#[derive(Deserialize, Debug)]
+pub struct CloneRequest {
+    /// Name of the new server
+    pub name: String,
+    /// Leave the worlds behind, the clone generates its own on start
+    #[serde(default)]
+    pub skip_worlds: bool,
+    /// Clone from one of the server's backups rather than how it is now
+    pub backup: Option<String>,
+    pub port: Option<u16>,
+}
+
+fn check_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.len() > 64 || name.starts_with('.') || name.starts_with('-')
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(Error::from("Not a valid server name"));
+    }
+    Ok(())
+}
+
+/// The lowest port above FIRST_PORT no other server is using
+pub fn free_port(ports: &[u16]) -> Result<u16, Error> {
+    let used: HashSet<&u16> = ports.iter().collect();
+    (FIRST_PORT..=u16::MAX).find(|p| !used.contains(p)).ok_or(Error::from("Ran out of ports"))
+}
+
+/// Copies a directory, leaving out the top level names in `skip`, symlinks are copied as links
+fn copy_dir(from: &Path, to: &Path, skip: &HashSet<String>) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if skip.contains(name.to_string_lossy().as_ref()) {
+            continue;
+        }
+        let (source, target) = (entry.path(), to.join(&name));
+        let kind = entry.file_type()?;
+        if kind.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(fs::read_link(&source)?, &target)?;
+        } else if kind.is_dir() {
+            copy_dir(&source, &target, &HashSet::new())?;
+        } else {
+            fs::copy(&source, &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Every world folder of a server, along with the other dimensions of Bukkit worlds
+fn world_dirs(server: &Server) -> HashSet<String> {
+    worlds::list(server).unwrap_or_default().iter()
+        .flat_map(|w| worlds::dimensions(server, &w.name))
+        .collect()
+}
+
+/// Clears out what mc-docker keeps about the original, apart from the record of installed mods
+/// since those came along
+fn drop_records(path: &str) {
+    let entries = fs::read_dir(format!("{path}/mc-docker")).map(|d| d.filter_map(|e| e.ok()).collect::<Vec<_>>());
+    for entry in entries.unwrap_or_default() {
+        if entry.file_name() == MANIFEST {
+            continue;
+        }
+        let _ = if entry.path().is_dir() { fs::remove_dir_all(entry.path()) } else { fs::remove_file(entry.path()) };
+    }
+}
+
+/// Copies the files of a server to where the clone will live, returning that path
+pub async fn copy(source: &Server, request: &CloneRequest, config: &Config) -> Result<String, Error> {
+    check_name(&request.name)?;
+    // Only the game port gets a new number on the clone, anything else it publishes would clash
+    // with the original
+    if source.ports()?.len() > 1 {
+        return Err(Error::from("Can't clone a server that publishes more than the game port"));
+    }
+    let path = format!("{}/{}", config.path, request.name);
+    if Path::new(&path).exists() {
+        return Err(Error::from("Something already exists where the clone would go"));
+    }
+
+    let result = match &request.backup {
+        Some(backup) => from_backup(source, request, config, backup, &path).await,
+        None => from_live(source, request, &path).await,
+    };
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&path);
+    }
+    result.map(|_| path)
+}
+
+async fn from_backup(source: &Server, request: &CloneRequest, config: &Config, backup: &str, path: &str) -> Result<(), Error> {
+    if backup.contains('/') || backup.contains('\\') || backup.starts_with('.') {
+        return Err(Error::from("Not a valid backup name"));
+    }
+    let archive = format!("{}/{}/{backup}", config.backup_path(), source.name);
+    if !Path::new(&archive).is_file() {
+        return Err(Error::from("Backup doesn't exist"));
+    }
+    if let Err(_) = fs::create_dir_all(path) {
+        return Err(Error::from("Error creating the directory for the clone"));
+    }
+
+    let output = tokio::process::Command::new("tar")
+        .arg("-xzf").arg(&archive)
+        .arg("-C").arg(path)
+        .output()
+        .await;
+    match output {
+        Ok(o) if o.status.success() => {},
+        _ => return Err(Error::from("Failed to extract the backup")),
+    }
+
+    drop_records(path);
+    if request.skip_worlds {
+        for dir in fs::read_dir(path).map(|d| d.filter_map(|e| e.ok()).collect::<Vec<_>>()).unwrap_or_default() {
+            if dir.path().join("level.dat").is_file() {
+                let _ = fs::remove_dir_all(dir.path());
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn from_live(source: &Server, request: &CloneRequest, path: &str) -> Result<(), Error> {
+    let mut skip: HashSet<String> = HashSet::from(["mc-docker".to_string()]);
+    if request.skip_worlds {
+        skip.extend(world_dirs(source));
+    }
+
+    // Same as a backup, nothing gets written to the world while it's copied. This fails when the
+    // server isn't running, which is fine since nothing is writing then either
+    let live = !request.skip_worlds && source.send_command(vec!["save-off".to_string()]).await.is_ok();
+    if live {
+        let _ = source.send_command(vec!["save-all".to_string(), "flush".to_string()]).await;
+    }
+
+    let (from, to) = (source.path.clone(), path.to_string());
+    let copied = tokio::task::spawn_blocking(move || copy_dir(Path::new(&from), Path::new(&to), &skip)).await;
+
+    if live {
+        let _ = source.send_command(vec!["save-on".to_string()]).await;
+    }
+    match copied {
+        Ok(Ok(_)) => {},
+        _ => return Err(Error::from("Failed to copy the server directory")),
+    }
+
+    // Installed mods came along, so the record of where they came from should too, the same as
+    // what's left after drop_records on a backup
+    let manifest = format!("{}/mc-docker/{MANIFEST}", source.path);
+    if Path::new(&manifest).is_file() {
+        let _ = fs::create_dir_all(format!("{path}/mc-docker"));
+        let _ = fs::copy(&manifest, format!("{path}/mc-docker/{MANIFEST}"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_ports() {
+        assert_eq!(free_port(&[]).unwrap(), FIRST_PORT);
+        assert_eq!(free_port(&[25565, FIRST_PORT, FIRST_PORT + 2]).unwrap(), FIRST_PORT + 1);
+    }
+
+    #[test]
+    fn only_the_manifest_is_kept() {
+        let path = std::env::temp_dir().join(format!("mc-docker-clone-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(path.join("mc-docker/crashes")).unwrap();
+        for file in ["mc-docker/modrinth.json", "mc-docker/players.json", "mc-docker/crashes/1.json", "server.properties"] {
+            fs::write(path.join(file), "").unwrap();
+        }
+
+        drop_records(&path.to_string_lossy());
+        let mut left: Vec<String> = fs::read_dir(path.join("mc-docker")).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        let properties = path.join("server.properties").is_file();
+        let _ = fs::remove_dir_all(&path);
+        assert_eq!(left, vec!["modrinth.json"]);
+        assert!(properties);
+    }
+}
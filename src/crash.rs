@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::SystemTime;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{sleep, Duration};
+use crate::{Servers, Events, Logs, States};
+use crate::events::{Event, Lifecycle};
+use crate::server::Server;
+use crate::error::Error;
+
+// Lines of output saved with every crash
+const LOG_LINES: usize = 100;
+// No restart waits longer than an hour, however many times the server has crashed
+const MAX_DELAY: u64 = 3600;
+const MAX_ATTEMPTS: u32 = 100;
+
+/// Whether a server is brought back up after it crashes, stored with the server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AutoRestart {
+    pub enabled: bool,
+    /// Crashes in a row before giving up
+    pub max_attempts: u32,
+    /// Seconds before the first restart, doubled for every attempt after
+    pub backoff: u64,
+    /// Seconds the server has to stay up for the attempts to be reset
+    pub reset_after: u64,
+}
+
+impl Default for AutoRestart {
+    fn default() -> AutoRestart {
+        AutoRestart {
+            enabled: false,
+            max_attempts: 3,
+            backoff: 10,
+            reset_after: 600,
+        }
+    }
+}
+
+impl AutoRestart {
+    /// Keeps the numbers to something that makes sense, they come straight from the API
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.max_attempts > MAX_ATTEMPTS {
+            return Err(Error::from("max_attempts can be at most 100"));
+        }
+        if self.backoff == 0 || self.backoff > MAX_DELAY {
+            return Err(Error::from("backoff has to be between 1 and 3600 seconds"));
+        }
+        Ok(())
+    }
+
+    /// Seconds to wait before restarting after `crashes` crashes in a row
+    pub fn delay(&self, crashes: u32) -> u64 {
+        2u64.checked_pow(crashes)
+            .map(|m| self.backoff.saturating_mul(m))
+            .unwrap_or(MAX_DELAY)
+            .min(MAX_DELAY)
+    }
+}
+
+/// Everything we could find out about a crash, saved next to the server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CrashRecord {
+    pub server: String,
+    pub time: DateTime<Utc>,
+    pub exit_code: Option<i64>,
+    pub oom_killed: bool,
+    pub report_file: Option<String>,
+    pub report: Option<String>,
+    pub log: Vec<String>,
+    pub restart_attempt: Option<u32>,
+}
+
+fn records_dir(server: &Server) -> String {
+    format!("{}/mc-docker/crashes", server.path)
+}
+
+/// Every crash recorded for a server, most recent first
+pub fn records(server: &Server) -> Result<Vec<CrashRecord>, Error> {
+    let dir = match fs::read_dir(records_dir(server)) {
+        Ok(d) => d,
+        // Never crashed
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let mut records: Vec<CrashRecord> = dir
+        .filter_map(|e| e.ok())
+        .filter_map(|e| fs::read_to_string(e.path()).ok())
+        .filter_map(|f| serde_json::from_str(&f).ok())
+        .collect();
+    records.sort_by_key(|r| std::cmp::Reverse(r.time));
+    Ok(records)
+}
+
+fn save(server: &Server, record: &CrashRecord) -> Result<(), Error> {
+    let dir = records_dir(server);
+    if let Err(_) = fs::create_dir_all(&dir) {
+        return Err(Error::from("Error creating the crash record directory"));
+    }
+    let json = if let Ok(j) = serde_json::to_string_pretty(record) { j } else {
+        return Err(Error::from("Error serializing crash record"));
+    };
+    if let Err(_) = fs::write(format!("{dir}/{}.json", record.time.format("%Y%m%d-%H%M%S")), json) {
+        return Err(Error::from("Error writing crash record"));
+    }
+    Ok(())
+}
+
+/// The newest file in crash-reports, as long as it was written after the server came up
+fn latest_report(server: &Server, since: Option<DateTime<Utc>>) -> Option<(String, String)> {
+    let (path, modified) = fs::read_dir(format!("{}/crash-reports", server.path)).ok()?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().ends_with(".txt"))
+        .filter_map(|e| Some((e.path(), e.metadata().ok()?.modified().ok()?)))
+        .max_by_key(|(_, m)| *m)?;
+
+    let started: SystemTime = since.map(SystemTime::from).unwrap_or(SystemTime::UNIX_EPOCH);
+    if modified < started {
+        return None;
+    }
+    let name = path.file_name()?.to_string_lossy().to_string();
+    Some((name, fs::read_to_string(&path).ok()?))
+}
+
+/// Records every crash and restarts the servers that ask for it
+pub async fn monitor(servers: Servers, states: States, logs: Logs, events: Events) {
+    // name -> crashes in a row
+    let mut attempts: HashMap<String, u32> = HashMap::new();
+    let (_, mut rx) = events.subscribe(None);
+
+    loop {
+        let envelope = match rx.recv().await {
+            Ok(e) => e,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return,
+        };
+        let name = envelope.server;
+
+        match envelope.event.event {
+            Event::Crash { .. } => states.note_crash(&name),
+            Event::Lifecycle { state: Lifecycle::Crashed } => {
+                let server = if let Some(s) = servers.read().await.get(&name) { s.clone() } else { continue };
+                let state = states.get(&name).unwrap_or_default();
+                println!("{name} crashed with exit code {:?}", state.exit_code);
+
+                // The attempts start over once the server managed to stay up for a while
+                let crashes = attempts.entry(name.clone()).or_insert(0);
+                let uptime = state.started.map(|s| (Utc::now() - s).num_seconds()).unwrap_or(0);
+                if uptime > server.auto_restart.reset_after as i64 {
+                    *crashes = 0;
+                }
+
+                let restart = server.auto_restart.enabled && *crashes < server.auto_restart.max_attempts;
+                let report = latest_report(&server, state.started);
+                let log = logs.recent(&name);
+                let record = CrashRecord {
+                    server: name.clone(),
+                    time: Utc::now(),
+                    exit_code: state.exit_code,
+                    oom_killed: state.oom_killed,
+                    report_file: report.as_ref().map(|r| r.0.clone()),
+                    report: report.map(|r| r.1),
+                    log: log[log.len().saturating_sub(LOG_LINES)..].to_vec(),
+                    restart_attempt: if restart { Some(*crashes + 1) } else { None },
+                };
+                if let Err(e) = save(&server, &record) {
+                    println!("Failed to save crash record for {name}: {:?}", e);
+                }
+
+                if restart {
+                    let delay = server.auto_restart.delay(*crashes);
+                    *crashes += 1;
+                    println!("Restarting {name} in {delay}s (attempt {})", crashes);
+                    tokio::spawn(restart_later(server, states.clone(), delay));
+                } else if server.auto_restart.enabled {
+                    println!("{name} crashed {} times in a row, not restarting it again", crashes);
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+async fn restart_later(server: Server, states: States, delay: u64) {
+    sleep(Duration::from_secs(delay)).await;
+    // Docker's own restart policy may have beaten us to it
+    if states.is_running(&server).await {
+        return;
+    }
+    if let Err(e) = server.start().await {
+        println!("Failed to restart {} after a crash: {:?}", server.name, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auto_restart(max_attempts: u32, backoff: u64) -> AutoRestart {
+        AutoRestart { enabled: true, max_attempts, backoff, reset_after: 600 }
+    }
+
+    #[test]
+    fn delay_doubles_up_to_an_hour() {
+        let restart = auto_restart(100, 10);
+        assert_eq!((0..5).map(|c| restart.delay(c)).collect::<Vec<_>>(), [10, 20, 40, 80, 160]);
+        assert_eq!(restart.delay(9), MAX_DELAY);
+        // Would overflow without the checks
+        assert_eq!(restart.delay(64), MAX_DELAY);
+        assert_eq!(restart.delay(u32::MAX), MAX_DELAY);
+        assert_eq!(auto_restart(100, MAX_DELAY).delay(63), MAX_DELAY);
+    }
+
+    #[test]
+    fn validate() {
+        assert!(AutoRestart::default().validate().is_ok());
+        assert!(auto_restart(100, 3600).validate().is_ok());
+        assert!(auto_restart(101, 10).validate().is_err());
+        assert!(auto_restart(3, 0).validate().is_err());
+        assert!(auto_restart(3, 3601).validate().is_err());
+    }
+}
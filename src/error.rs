@@ -1,6 +1,6 @@
 use warp::{
     Rejection,
-    reject::Reject,
+    reject::{Reject, InvalidHeader},
     Reply,
     reply,
     http::StatusCode
@@ -14,6 +14,12 @@ pub struct NotRegistered {
 }
 impl Reject for NotRegistered {}
 
+#[derive(Debug)]
+pub struct Forbidden {
+    pub reason: String,
+}
+impl Reject for Forbidden {}
+
 #[derive(Debug)]
 pub struct Dummy;
 impl Reject for Dummy {}
@@ -40,20 +46,34 @@ impl Reject for Error {}
 pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
     let code;
     let message;
+    let kind;
 
     if err.is_not_found() {
         code = StatusCode::NOT_FOUND;
         message = "NOT_FOUND".to_string();
+        kind = "not_found";
+    } else if let Some(e) = err.find::<InvalidHeader>() {
+        code = StatusCode::BAD_REQUEST;
+        message = e.to_string();
+        kind = "bad_request";
+    } else if let Some(Forbidden {reason}) = err.find() {
+        code = StatusCode::FORBIDDEN;
+        message = reason.to_string();
+        kind = "forbidden";
     } else if let Some(NotRegistered {id}) = err.find() {
         code = StatusCode::INTERNAL_SERVER_ERROR;
         message = format!("Server is not registered: {id}");
+        kind = "not_registered";
     } else if let Some(Error {reason}) = err.find() {
         code = StatusCode::INTERNAL_SERVER_ERROR;
         message = format!("Server Error: {reason}");
+        kind = "server_error";
     } else {
         code = StatusCode::IM_A_TEAPOT;
         message = "Unhandled Rejection???".to_string();
+        kind = "unhandled";
     }
+    crate::metrics::record_error(code.as_u16(), kind);
 
     let json = reply::json(&ErrorMessage {
         code: code.as_u16(),
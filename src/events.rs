@@ -0,0 +1,284 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, TimeZone};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use lazy_static::lazy_static;
+
+/// Something that happened on a server, parsed out of a line of its output
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LogEvent {
+    pub time: DateTime<Local>,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Chat { player: String, message: String },
+    Join { player: String },
+    Leave { player: String },
+    Death { player: String, message: String },
+    Advancement { player: String, advancement: String },
+    Started { seconds: f64 },
+    Lag { ms: u64, ticks: u64 },
+    Crash { message: String, report: Option<String> },
+    Lifecycle { state: Lifecycle },
+}
+
+/// Changes in whether a server is up, these come from mc-docker rather than the log
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Lifecycle {
+    Starting,
+    Stopping,
+    Stopped,
+    Restarting,
+    Crashed,
+}
+
+impl Event {
+    /// The name of the event as it's serialized, used for filtering
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Chat { .. } => "chat",
+            Event::Join { .. } => "join",
+            Event::Leave { .. } => "leave",
+            Event::Death { .. } => "death",
+            Event::Advancement { .. } => "advancement",
+            Event::Started { .. } => "started",
+            Event::Lag { .. } => "lag",
+            Event::Crash { .. } => "crash",
+            Event::Lifecycle { .. } => "lifecycle",
+        }
+    }
+
+    /// How chat, joins and leaves read in game, used for relaying them elsewhere
+    pub fn chat_line(&self) -> Option<String> {
+        match self {
+            Event::Chat { player, message } => Some(format!("<{player}> {message}")),
+            Event::Join { player } => Some(format!("{player} joined the game")),
+            Event::Leave { player } => Some(format!("{player} left the game")),
+            _ => None,
+        }
+    }
+}
+
+lazy_static! {
+    // Docker puts this in front of every line when asked for timestamps
+    static ref DOCKER_TIME: Regex = Regex::new(r"^(?P<time>\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(?:\.\d+)?Z) (?P<line>.*)$").unwrap();
+
+    // Terminal colours and the console prompt that paper prints since the containers run with a tty
+    static ref ANSI: Regex = Regex::new(r"\x1b\[[0-9;?]*[A-Za-z]|^[\r>\s]+").unwrap();
+
+    // Covers the line prefixes of
+    // vanilla/fabric: [12:34:56] [Server thread/INFO]: msg
+    // paper/spigot:   [12:34:56 INFO]: msg
+    // forge:          [19Mar2023 12:34:56.789] [Server thread/INFO] [net.minecraft.server.MinecraftServer/]: msg
+    static ref LINE: Regex = Regex::new(
+        r"^\[(?:(?P<date>\d{1,2}[A-Za-z]{3}\d{4}) )?(?P<time>\d{2}:\d{2}:\d{2})(?:\.\d+)?(?: (?P<plevel>[A-Z]+))?\](?: \[(?P<thread>[^\]]*)/(?P<level>[A-Z]+)\])?(?: \[[^\]]*\])?: (?P<msg>.*)$"
+    ).unwrap();
+
+    static ref CHAT: Regex = Regex::new(r"^(?:\[Not Secure\] )?<(?P<player>[^>]+)> (?P<msg>.*)$").unwrap();
+    static ref JOIN: Regex = Regex::new(r"^(?P<player>\w{1,16}) joined the game$").unwrap();
+    static ref LEAVE: Regex = Regex::new(r"^(?P<player>\w{1,16}) left the game$").unwrap();
+    static ref ADVANCEMENT: Regex = Regex::new(
+        r"^(?P<player>\w{1,16}) has (?:made the advancement|completed the challenge|reached the goal) \[(?P<name>.+)\]$"
+    ).unwrap();
+    static ref STARTED: Regex = Regex::new(r#"^Done \((?P<secs>[\d.]+)s\)! For help, type "help""#).unwrap();
+    static ref LAG: Regex = Regex::new(
+        r"^Can't keep up! Is the server overloaded\? Running (?P<ms>\d+)ms or (?P<ticks>\d+) ticks behind"
+    ).unwrap();
+    static ref CRASH: Regex = Regex::new(
+        r"^(?:This crash report has been saved to: (?P<report>.+)|Encountered an unexpected exception.*|Failed to start the minecraft server.*)$"
+    ).unwrap();
+    // Every vanilla death message starts with the player name followed by one of these
+    static ref DEATH: Regex = Regex::new(
+        r"^(?P<player>\w{1,16}) (?:was |died|drowned|blew up|burned to death|went up in flames|went off with a bang|walked into |suffocated|starved to death|hit the ground too hard|experienced kinetic energy|froze to death|withered away|tried to swim in lava|discovered the floor was lava|didn't want to live|left the confines of this world|fell )"
+    ).unwrap();
+}
+
+impl LogEvent {
+    pub fn now(event: Event) -> LogEvent {
+        LogEvent { time: Local::now(), event }
+    }
+
+    pub fn lifecycle(state: Lifecycle) -> LogEvent {
+        LogEvent::now(Event::Lifecycle { state })
+    }
+}
+
+/// Removes the colour codes and console prompt from a raw line of output
+pub fn strip(line: &str) -> String {
+    ANSI.replace_all(line, "").trim_end().to_string()
+}
+
+/// Parses a line of server output, returning None for anything that isn't an event we track
+pub fn parse(line: &str) -> Option<LogEvent> {
+    let (docker_time, line) = match DOCKER_TIME.captures(line) {
+        Some(c) => (
+            DateTime::parse_from_rfc3339(&c["time"]).ok().map(|t| t.with_timezone(&Local)),
+            strip(&c["line"]),
+        ),
+        None => (None, strip(line)),
+    };
+    let caps = LINE.captures(&line)?;
+    let msg = caps.name("msg")?.as_str();
+    let level = caps.name("level").or(caps.name("plevel")).map(|l| l.as_str()).unwrap_or("INFO");
+    let time = docker_time.unwrap_or(timestamp(caps.name("date").map(|d| d.as_str()), &caps["time"]));
+
+    let event = if let Some(c) = CHAT.captures(msg) {
+        Event::Chat { player: c["player"].to_string(), message: c["msg"].to_string() }
+    } else if let Some(c) = JOIN.captures(msg) {
+        Event::Join { player: c["player"].to_string() }
+    } else if let Some(c) = LEAVE.captures(msg) {
+        Event::Leave { player: c["player"].to_string() }
+    } else if let Some(c) = ADVANCEMENT.captures(msg) {
+        Event::Advancement { player: c["player"].to_string(), advancement: c["name"].to_string() }
+    } else if let Some(c) = STARTED.captures(msg) {
+        Event::Started { seconds: c["secs"].parse().unwrap_or(0.0) }
+    } else if let Some(c) = LAG.captures(msg) {
+        Event::Lag { ms: c["ms"].parse().unwrap_or(0), ticks: c["ticks"].parse().unwrap_or(0) }
+    } else if let Some(c) = CRASH.captures(msg) {
+        Event::Crash { message: msg.to_string(), report: c.name("report").map(|r| r.as_str().to_string()) }
+    } else if let Some(c) = DEATH.captures(msg).filter(|_| level == "INFO") {
+        Event::Death { player: c["player"].to_string(), message: msg.to_string() }
+    } else {
+        return None;
+    };
+
+    Some(LogEvent { time, event })
+}
+
+/// Logs only carry the time of day (forge adds the date), so the rest is filled in from now
+fn timestamp(date: Option<&str>, time: &str) -> DateTime<Local> {
+    let now = Local::now();
+    let day = date
+        .and_then(|d| NaiveDate::parse_from_str(d, "%d%b%Y").ok())
+        .unwrap_or(now.date_naive());
+
+    NaiveTime::parse_from_str(time, "%H:%M:%S")
+        .ok()
+        .and_then(|t| Local.from_local_datetime(&day.and_time(t)).single())
+        .unwrap_or(now)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Datelike, Timelike};
+    use super::*;
+
+    fn event(line: &str) -> Option<Event> {
+        parse(line).map(|e| e.event)
+    }
+
+    fn chat(player: &str, message: &str) -> Option<Event> {
+        Some(Event::Chat { player: player.to_string(), message: message.to_string() })
+    }
+
+    fn join(player: &str) -> Option<Event> {
+        Some(Event::Join { player: player.to_string() })
+    }
+
+    fn leave(player: &str) -> Option<Event> {
+        Some(Event::Leave { player: player.to_string() })
+    }
+
+    fn death(player: &str, message: &str) -> Option<Event> {
+        Some(Event::Death { player: player.to_string(), message: message.to_string() })
+    }
+
+    #[test]
+    fn vanilla() {
+        let cases = [
+            ("[12:34:56] [Server thread/INFO]: <Steve> hello there", chat("Steve", "hello there")),
+            ("[12:34:56] [Server thread/INFO]: [Not Secure] <Steve> hi", chat("Steve", "hi")),
+            ("[12:34:56] [Server thread/INFO]: <Steve> Alex joined the game", chat("Steve", "Alex joined the game")),
+            ("[12:34:56] [Server thread/INFO]: Steve joined the game", join("Steve")),
+            ("[12:34:56] [Server thread/INFO]: Steve left the game", leave("Steve")),
+            ("[12:34:56] [Server thread/INFO]: Steve was slain by Zombie", death("Steve", "Steve was slain by Zombie")),
+            ("[12:34:56] [Server thread/INFO]: Steve fell from a high place", death("Steve", "Steve fell from a high place")),
+            ("[12:34:56] [Server thread/INFO]: Steve tried to swim in lava", death("Steve", "Steve tried to swim in lava")),
+            ("[12:34:56] [Server thread/INFO]: Steve has made the advancement [Stone Age]",
+                Some(Event::Advancement { player: "Steve".to_string(), advancement: "Stone Age".to_string() })),
+            ("[12:34:56] [Server thread/INFO]: Done (12.345s)! For help, type \"help\"", Some(Event::Started { seconds: 12.345 })),
+            ("[12:34:56] [Server thread/WARN]: Can't keep up! Is the server overloaded? Running 5123ms or 102 ticks behind",
+                Some(Event::Lag { ms: 5123, ticks: 102 })),
+            ("[12:34:56] [Server thread/ERROR]: This crash report has been saved to: /data/crash-reports/crash-2023-03-19_12.34.56-server.txt",
+                Some(Event::Crash {
+                    message: "This crash report has been saved to: /data/crash-reports/crash-2023-03-19_12.34.56-server.txt".to_string(),
+                    report: Some("/data/crash-reports/crash-2023-03-19_12.34.56-server.txt".to_string()),
+                })),
+            ("[12:34:56] [Server thread/ERROR]: Encountered an unexpected exception",
+                Some(Event::Crash { message: "Encountered an unexpected exception".to_string(), report: None })),
+            // Lines that look close but aren't events
+            ("[12:34:56] [Server thread/INFO]: Steve lost connection: Disconnected", None),
+            ("[12:34:56] [Server thread/INFO]: Preparing spawn area: 50%", None),
+            ("[12:34:56] [Server thread/WARN]: Steve was kicked for floating too long!", None),
+            ("[12:34:56] [Server thread/INFO]: Starting minecraft server version 1.20.1", None),
+            ("Starting net.minecraft.server.Main", None),
+            ("", None),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(event(line), expected, "{line}");
+        }
+    }
+
+    #[test]
+    fn paper() {
+        let cases = [
+            ("[12:34:56 INFO]: <Alex> hi", chat("Alex", "hi")),
+            ("[12:34:56 INFO]: Alex joined the game", join("Alex")),
+            ("[12:34:56 INFO]: Alex left the game", leave("Alex")),
+            ("[12:34:56 INFO]: Alex drowned", death("Alex", "Alex drowned")),
+            ("[12:34:56 INFO]: Done (3.210s)! For help, type \"help\"", Some(Event::Started { seconds: 3.21 })),
+            ("[12:34:56 WARN]: Can't keep up! Is the server overloaded? Running 2500ms or 50 ticks behind",
+                Some(Event::Lag { ms: 2500, ticks: 50 })),
+            // With a tty paper colours its output and redraws the console prompt
+            ("\x1b[33m[12:34:56 WARN]: Can't keep up! Is the server overloaded? Running 2500ms or 50 ticks behind\x1b[m",
+                Some(Event::Lag { ms: 2500, ticks: 50 })),
+            ("\r> [12:34:56 INFO]: Alex joined the game", join("Alex")),
+            ("[12:34:56 INFO]: \x1b[93mAlex joined the game\x1b[0m\r", join("Alex")),
+            ("[12:34:56 WARN]: Alex was kicked for floating too long!", None),
+            ("[12:34:56 INFO]: UUID of player Alex is 853c80ef-3c37-49fd-aa49-938b674adae6", None),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(event(line), expected, "{line:?}");
+        }
+    }
+
+    #[test]
+    fn forge() {
+        let cases = [
+            ("[19Mar2023 12:34:56.789] [Server thread/INFO] [net.minecraft.server.MinecraftServer/]: <Steve> hey", chat("Steve", "hey")),
+            ("[19Mar2023 12:34:56.789] [Server thread/INFO] [net.minecraft.server.MinecraftServer/]: Steve joined the game", join("Steve")),
+            ("[19Mar2023 12:34:56.789] [Server thread/INFO] [net.minecraft.server.MinecraftServer/]: Steve left the game", leave("Steve")),
+            ("[19Mar2023 12:34:56.789] [Server thread/INFO] [net.minecraft.server.MinecraftServer/]: Steve blew up",
+                death("Steve", "Steve blew up")),
+            ("[19Mar2023 12:34:56.789] [Server thread/INFO] [net.minecraft.server.dedicated.DedicatedServer/]: Done (25.123s)! For help, type \"help\"",
+                Some(Event::Started { seconds: 25.123 })),
+            ("[19Mar2023 12:34:56.789] [Server thread/ERROR] [net.minecraft.server.MinecraftServer/]: Encountered an unexpected exception",
+                Some(Event::Crash { message: "Encountered an unexpected exception".to_string(), report: None })),
+            ("[19Mar2023 12:34:56.789] [main/INFO] [cpw.mods.modlauncher.Launcher/MODLAUNCHER]: ModLauncher running", None),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(event(line), expected, "{line}");
+        }
+    }
+
+    #[test]
+    fn times() {
+        // Forge has the date in the line
+        let forge = parse("[19Mar2023 12:34:56.789] [Server thread/INFO] [net.minecraft.server.MinecraftServer/]: Steve joined the game").unwrap();
+        assert_eq!((forge.time.year(), forge.time.month(), forge.time.day()), (2023, 3, 19));
+        assert_eq!((forge.time.hour(), forge.time.minute(), forge.time.second()), (12, 34, 56));
+
+        // Others only have the time of day
+        let paper = parse("[01:02:03 INFO]: Alex joined the game").unwrap();
+        assert_eq!((paper.time.hour(), paper.time.minute(), paper.time.second()), (1, 2, 3));
+
+        // Docker's timestamp wins when it's there
+        let docker = parse("2023-03-19T12:34:56.123456789Z [01:02:03 INFO]: Alex joined the game").unwrap();
+        assert_eq!(docker.event, Event::Join { player: "Alex".to_string() });
+        assert_eq!(docker.time, DateTime::parse_from_rfc3339("2023-03-19T12:34:56.123456789Z").unwrap());
+    }
+}
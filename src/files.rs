@@ -0,0 +1,388 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt, future, stream};
+use hyper::body::{Buf, Bytes};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+use crate::error::Error;
+use crate::server::Server;
+
+/// Biggest file that can be uploaded in one go
+pub const MAX_UPLOAD: u64 = 512 * 1024 * 1024;
+/// Biggest directory that can be downloaded as a zip, worlds should go through backups
+pub const MAX_ZIP: u64 = 4 * 1024 * 1024 * 1024;
+
+/// A path inside the server directory, the root when left out
+#[derive(Deserialize, Debug, Default)]
+pub struct FileQuery {
+    #[serde(default)]
+    pub path: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Entry {
+    pub name: String,
+    pub dir: bool,
+    pub size: u64,
+    pub modified: Option<DateTime<Utc>>,
+}
+
+/// Turns a path from a request into one inside the server directory, refusing anything that would
+/// end up outside of it, `..` and symlinks included
+pub fn resolve(server: &Server, path: &str) -> Result<PathBuf, Error> {
+    let base = if let Ok(b) = fs::canonicalize(&server.path) { b } else {
+        return Err(Error::from("Server directory doesn't exist"));
+    };
+
+    let mut resolved = base.clone();
+    for component in Path::new(path.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(c) => resolved.push(c),
+            Component::CurDir => {},
+            _ => return Err(Error::from("Path has to stay inside the server directory")),
+        }
+    }
+
+    // Whatever part of it exists already can't lead anywhere else through a symlink, a link that
+    // leads nowhere counts as existing so it can't be written through later
+    let mut existing = resolved.as_path();
+    while fs::symlink_metadata(existing).is_err() {
+        existing = if let Some(p) = existing.parent() { p } else { break };
+    }
+    match fs::canonicalize(existing) {
+        Ok(c) if c.starts_with(&base) => Ok(resolved),
+        _ => Err(Error::from("Path has to stay inside the server directory")),
+    }
+}
+
+/// Runs filesystem work off the runtime, deleting a world or walking a big directory takes a while
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T, Error> + Send + 'static) -> Result<T, Error> {
+    tokio::task::spawn_blocking(f).await.unwrap_or(Err(Error::from("Filesystem task failed")))
+}
+
+fn is_root(server: &Server, path: &Path) -> bool {
+    fs::canonicalize(&server.path).map(|b| b == path).unwrap_or(true)
+}
+
+pub async fn list(server: &Server, path: &str) -> Result<Vec<Entry>, Error> {
+    let (server, path) = (server.clone(), path.to_string());
+    blocking(move || {
+        let dir = if let Ok(d) = fs::read_dir(resolve(&server, &path)?) { d } else {
+            return Err(Error::from("Failed to read the directory"));
+        };
+        let mut entries: Vec<Entry> = dir
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let meta = e.metadata().ok()?;
+                Some(Entry {
+                    name: e.file_name().to_string_lossy().to_string(),
+                    dir: meta.is_dir(),
+                    size: meta.len(),
+                    modified: meta.modified().ok().map(DateTime::<Utc>::from),
+                })
+            })
+            .collect();
+        entries.sort_by(|a, b| b.dir.cmp(&a.dir).then(a.name.cmp(&b.name)));
+        Ok(entries)
+    }).await
+}
+
+/// The contents of a file as a stream, along with its name and size
+pub async fn download(server: &Server, path: &str) -> Result<(String, u64, ReaderStream<tokio::fs::File>), Error> {
+    let path = resolve(server, path)?;
+    if !path.is_file() {
+        return Err(Error::from("Not a file"));
+    }
+    let file = if let Ok(f) = tokio::fs::File::open(&path).await { f } else {
+        return Err(Error::from("Failed to open the file"));
+    };
+    let size = file.metadata().await.map(|m| m.len()).unwrap_or(0);
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    Ok((name, size, ReaderStream::new(file)))
+}
+
+/// Writes an upload next to where it's going first, so a broken upload never replaces a good file
+pub async fn upload<S, B>(server: &Server, path: &str, mut body: S) -> Result<u64, Error>
+    where S: Stream<Item = Result<B, warp::Error>> + Unpin, B: Buf
+{
+    let path = resolve(server, path)?;
+    if path.is_dir() || is_root(server, &path) {
+        return Err(Error::from("Can't upload over a directory"));
+    }
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let partial = path.with_file_name(format!(".{name}.upload"));
+
+    let mut file = if let Ok(f) = tokio::fs::File::create(&partial).await { f } else {
+        return Err(Error::from("Failed to create the file, does its directory exist?"));
+    };
+    let mut written: u64 = 0;
+    let mut failed = None;
+    while let Some(chunk) = body.next().await {
+        let mut chunk = match chunk {
+            Ok(c) => c,
+            Err(_) => { failed = Some("Upload was interrupted"); break },
+        };
+        written += chunk.remaining() as u64;
+        if written > MAX_UPLOAD {
+            failed = Some("File is too big");
+            break;
+        }
+        while chunk.has_remaining() {
+            let n = chunk.chunk().len();
+            if let Err(_) = file.write_all(chunk.chunk()).await {
+                failed = Some("Failed to write the file");
+                break;
+            }
+            chunk.advance(n);
+        }
+        if failed.is_some() {
+            break;
+        }
+    }
+
+    if failed.is_none() && file.flush().await.is_err() {
+        failed = Some("Failed to write the file");
+    }
+    drop(file);
+    if let Some(reason) = failed {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(Error::from(reason));
+    }
+    if let Err(_) = tokio::fs::rename(&partial, &path).await {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(Error::from("Failed to move the upload into place"));
+    }
+    Ok(written)
+}
+
+pub async fn delete(server: &Server, path: &str) -> Result<(), Error> {
+    let (server, path) = (server.clone(), path.to_string());
+    blocking(move || {
+        let path = resolve(&server, &path)?;
+        if is_root(&server, &path) {
+            return Err(Error::from("Can't delete the server directory itself"));
+        }
+        // Removes the link itself rather than following it
+        let result = match fs::symlink_metadata(&path) {
+            Ok(m) if m.is_dir() => fs::remove_dir_all(&path),
+            Ok(_) => fs::remove_file(&path),
+            Err(_) => return Err(Error::from("File doesn't exist")),
+        };
+        if let Err(_) = result {
+            return Err(Error::from("Failed to delete"));
+        }
+        Ok(())
+    }).await
+}
+
+pub async fn rename(server: &Server, rename: &Rename) -> Result<(), Error> {
+    let (server, rename) = (server.clone(), rename.clone());
+    blocking(move || {
+        let from = resolve(&server, &rename.from)?;
+        let to = resolve(&server, &rename.to)?;
+        if is_root(&server, &from) || is_root(&server, &to) {
+            return Err(Error::from("Can't move the server directory itself"));
+        }
+        if !from.exists() {
+            return Err(Error::from("File doesn't exist"));
+        }
+        if to.exists() {
+            return Err(Error::from("Something already exists there"));
+        }
+        if let Err(_) = fs::rename(&from, &to) {
+            return Err(Error::from("Failed to rename"));
+        }
+        Ok(())
+    }).await
+}
+
+pub async fn mkdir(server: &Server, path: &str) -> Result<(), Error> {
+    let (server, path) = (server.clone(), path.to_string());
+    blocking(move || {
+        let path = resolve(&server, &path)?;
+        if let Err(_) = fs::create_dir_all(&path) {
+            return Err(Error::from("Failed to create the directory"));
+        }
+        Ok(())
+    }).await
+}
+
+fn dir_size(path: &Path) -> u64 {
+    fs::read_dir(path).map(|dir| dir
+        .filter_map(|e| e.ok())
+        .filter_map(|e| Some((e.path(), e.file_type().ok()?, e.metadata().ok()?)))
+        .map(|(p, t, m)| if t.is_dir() { dir_size(&p) } else { m.len() })
+        .sum()
+    ).unwrap_or(0)
+}
+
+/// Zips a directory on the fly with `zip`, streaming it out as it's written. The stream ends in
+/// an error when zip fails, so a broken archive doesn't look like a whole one
+pub async fn zip(server: &Server, path: &str) -> Result<(String, impl Stream<Item = io::Result<Bytes>>), Error> {
+    let (owned, query) = (server.clone(), path.to_string());
+    let path = blocking(move || {
+        let path = resolve(&owned, &query)?;
+        if !path.is_dir() {
+            return Err(Error::from("Not a directory"));
+        }
+        if dir_size(&path) > MAX_ZIP {
+            return Err(Error::from("Directory is too big to zip, use a backup instead"));
+        }
+        Ok(path)
+    }).await?;
+
+    let child = tokio::process::Command::new("zip")
+        // -y keeps symlinks as links, so nothing outside the directory ends up in the zip
+        .args(["-q", "-r", "-y", "-", "."])
+        .current_dir(&path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = if let Ok(c) = child { c } else {
+        return Err(Error::from("Failed to run zip, is it installed?"));
+    };
+    let stdout = if let Some(s) = child.stdout.take() { s } else {
+        return Err(Error::from("Failed to read the output of zip"));
+    };
+    // Only waited on once everything it wrote is out, dropping the stream kills it
+    let exit = stream::once(async move {
+        match child.wait().await {
+            Ok(status) if status.success() => None,
+            _ => Some(Err(io::Error::other("zip failed"))),
+        }
+    }).filter_map(future::ready);
+
+    let name = if is_root(server, &path) { server.name.clone() } else {
+        path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+    };
+    Ok((format!("{name}.zip"), ReaderStream::new(stdout).chain(exit)))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::symlink;
+    use super::*;
+
+    /// A server directory with a world in it, next to a directory outside of it
+    struct Dirs {
+        root: PathBuf,
+        base: PathBuf,
+        server: Server,
+    }
+
+    impl Dirs {
+        fn new(name: &str) -> Dirs {
+            let root = std::env::temp_dir().join(format!("mc-docker-files-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("server/world")).unwrap();
+            fs::create_dir_all(root.join("outside")).unwrap();
+            fs::write(root.join("outside/secret"), "").unwrap();
+            let base = fs::canonicalize(root.join("server")).unwrap();
+            let server: Server = serde_json::from_value(serde_json::json!({
+                "name": name,
+                "id": "",
+                "path": base.to_string_lossy(),
+                "port": 25565,
+            })).unwrap();
+            Dirs { root, base, server }
+        }
+    }
+
+    impl Drop for Dirs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn inside() {
+        let dirs = Dirs::new("inside");
+        for (path, expected) in [
+            ("", ""),
+            ("/", ""),
+            ("world", "world"),
+            ("/world/level.dat", "world/level.dat"),
+            ("./world/./region", "world/region"),
+            ("not/made/yet", "not/made/yet"),
+            // Absolute paths are taken as starting at the server directory
+            ("/etc/passwd", "etc/passwd"),
+            ("//etc", "etc"),
+        ] {
+            assert_eq!(resolve(&dirs.server, path).unwrap(), dirs.base.join(expected), "{path}");
+        }
+    }
+
+    #[test]
+    fn parent_dirs() {
+        let dirs = Dirs::new("parent");
+        for path in ["..", "../outside/secret", "world/../../outside", "world/..", "/../etc", "./.."] {
+            assert!(resolve(&dirs.server, path).is_err(), "{path}");
+        }
+    }
+
+    #[test]
+    fn symlinks() {
+        let dirs = Dirs::new("symlinks");
+        symlink(dirs.root.join("outside"), dirs.base.join("out")).unwrap();
+        symlink(dirs.root.join("outside/secret"), dirs.base.join("world/secret")).unwrap();
+        symlink(dirs.root.join("outside/missing"), dirs.base.join("dangling")).unwrap();
+        symlink(dirs.base.join("world"), dirs.base.join("world-link")).unwrap();
+
+        for path in ["out", "out/secret", "out/new/file", "world/secret", "dangling", "dangling/file"] {
+            assert!(resolve(&dirs.server, path).is_err(), "{path}");
+        }
+        // Links that stay inside are fine
+        assert_eq!(resolve(&dirs.server, "world-link/level.dat").unwrap(), dirs.base.join("world-link/level.dat"));
+    }
+
+    #[test]
+    fn missing_server_directory() {
+        let dirs = Dirs::new("missing");
+        fs::remove_dir_all(&dirs.base).unwrap();
+        assert!(resolve(&dirs.server, "world").is_err());
+    }
+
+    #[tokio::test]
+    async fn changes_files() {
+        let dirs = Dirs::new("changes");
+        mkdir(&dirs.server, "plugins/config").await.unwrap();
+        fs::write(dirs.base.join("plugins/config/a.yml"), "a").unwrap();
+        let rename_to = |from: &str, to: &str| Rename { from: from.to_string(), to: to.to_string() };
+        rename(&dirs.server, &rename_to("plugins/config/a.yml", "plugins/b.yml")).await.unwrap();
+        assert!(rename(&dirs.server, &rename_to("plugins/missing", "plugins/c.yml")).await.is_err());
+        assert!(rename(&dirs.server, &rename_to("plugins/b.yml", "world")).await.is_err());
+
+        let names: Vec<(String, bool)> = list(&dirs.server, "plugins").await.unwrap().into_iter().map(|e| (e.name, e.dir)).collect();
+        assert_eq!(names, [("config".to_string(), true), ("b.yml".to_string(), false)]);
+
+        delete(&dirs.server, "plugins").await.unwrap();
+        assert!(!dirs.base.join("plugins").exists());
+        assert!(delete(&dirs.server, "").await.is_err());
+        assert!(delete(&dirs.server, "../outside").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn zips_directories() {
+        let dirs = Dirs::new("zip");
+        fs::write(dirs.base.join("world/level.dat"), "level").unwrap();
+        let (name, stream) = zip(&dirs.server, "world").await.unwrap();
+        assert_eq!(name, "world.zip");
+        let chunks: Vec<io::Result<Bytes>> = stream.collect().await;
+        assert!(chunks.iter().all(|c| c.is_ok()));
+        let archive: Vec<u8> = chunks.into_iter().flat_map(|c| c.unwrap()).collect();
+        // Local file header of the one file in it
+        assert_eq!(&archive[..4], b"PK\x03\x04");
+        assert!(zip(&dirs.server, "world/level.dat").await.is_err());
+    }
+}
@@ -1,11 +1,31 @@
-use futures::StreamExt;
-use crate::server::Server;
+use futures::{StreamExt, stream, future};
+use tokio_stream::wrappers::BroadcastStream;
+use crate::server::{Server, LogQuery};
+use crate::schedule::Schedule;
+use crate::events::{self, LogEvent, Lifecycle};
+use crate::chat::{ChatMessage, CHAT_EVENTS};
+use std::convert::Infallible;
+use cloudsync::CloudSync;
 use serde::{Serialize, Deserialize};
 use warp::{
     http::{StatusCode, Response},
     reply::json,
+    sse,
+    path::FullPath,
     Reply, Rejection, reject};
-use crate::{Servers, Config};
+use crate::{Servers, Config, Events, Logs, Modules, Webhooks, States, Stats, Ticks, Sessions};
+use crate::watcher::ServerState;
+use crate::stats::Resources;
+use crate::tps::Tick;
+use crate::limits::Limits;
+use crate::players::{self, PlayerList, PlayerAction};
+use crate::properties::{self, Properties};
+use crate::files::{self, FileQuery, Rename};
+use crate::modrinth::{self, Modrinth, Platform};
+use crate::worlds::{self, WorldQuery, Reset, Switch};
+use crate::upgrade::{self, Upgrade};
+use crate::clone::{self, CloneRequest};
+use crate::crash::{self, AutoRestart};
 use crate::error::*;
 
 type Result<T> = std::result::Result<T, Rejection>;
@@ -29,10 +49,33 @@ pub async fn start_handler(id: String, servers: Servers) -> Result<impl Reply> {
     }
 }
 
-pub async fn stop_handler(id: String, servers: Servers) -> Result<impl Reply> {
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StopMode {
+    #[default]
+    Graceful,
+    Immediate,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StopQuery {
+    #[serde(default)]
+    mode: StopMode,
+}
+
+pub async fn stop_handler(id: String, query: StopQuery, servers: Servers, config: Config, events: Events, states: States) -> Result<impl Reply> {
     println!("Stopped {id}");
-    if let Some(s) = servers.write().await.get(&id) {
-        match s.stop().await {
+    // Cloned out so a long countdown doesn't hold the lock on every other server
+    let server = servers.read().await.get(&id).cloned();
+    if let Some(s) = server {
+        events.publish(&id, LogEvent::lifecycle(Lifecycle::Stopping));
+        states.expect_stop(&id, &config.stop);
+        let stopped = match query.mode {
+            StopMode::Graceful => s.graceful_stop(&config.stop).await,
+            StopMode::Immediate => s.stop().await,
+        };
+        states.stop_done(&id);
+        match stopped {
             Ok(_) => Ok(StatusCode::OK),
             Err(e) => {
                 println!("Rejection on stop: {:?}", e);
@@ -44,15 +87,87 @@ pub async fn stop_handler(id: String, servers: Servers) -> Result<impl Reply> {
     }
 }
 
+pub async fn restart_handler(id: String, servers: Servers, config: Config, events: Events, states: States) -> Result<impl Reply> {
+    println!("Restarting {id}");
+    let server = servers.read().await.get(&id).cloned();
+    if let Some(s) = server {
+        events.publish(&id, LogEvent::lifecycle(Lifecycle::Restarting));
+        states.expect_stop(&id, &config.stop);
+        let restarted = s.restart(&config.stop).await;
+        states.stop_done(&id);
+        match restarted {
+            Ok(_) => Ok(StatusCode::OK),
+            Err(e) => {
+                println!("Rejection on restart: {:?}", e);
+                Err(reject::custom(e))
+            }
+        }
+    } else {
+        Err(reject::custom(NotRegistered { id }))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BackupResponse {
+    archive: String,
+}
+
+pub async fn backup_handler(id: String, servers: Servers, config: Config) -> Result<impl Reply> {
+    println!("Backing up {id}");
+    let server = servers.read().await.get(&id).cloned();
+    if let Some(s) = server {
+        match s.backup(&config).await {
+            Ok(archive) => Ok(json(&BackupResponse { archive })),
+            Err(e) => {
+                println!("Rejection on backup: {:?}", e);
+                Err(reject::custom(e))
+            }
+        }
+    } else {
+        Err(reject::custom(NotRegistered { id }))
+    }
+}
+
+pub async fn get_schedule_handler(id: String, servers: Servers) -> Result<impl Reply> {
+    if let Some(s) = servers.read().await.get(&id) {
+        Ok(json(&s.schedules))
+    } else {
+        Err(reject::custom(NotRegistered { id }))
+    }
+}
+
+pub async fn set_schedule_handler(id: String, body: Vec<Schedule>, servers: Servers) -> Result<impl Reply> {
+    println!("Updating schedules for {id}");
+    for schedule in body.iter() {
+        if let Err(e) = schedule.validate() {
+            return Err(reject::custom(e));
+        }
+    }
+    if let Some(s) = servers.write().await.get_mut(&id) {
+        s.schedules = body;
+        if let Err(_) = s.save().await {
+            return Err(reject::custom(Error::from("Failed to save schedules to firebase")));
+        }
+        Ok(StatusCode::OK)
+    } else {
+        Err(reject::custom(NotRegistered { id }))
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)] pub struct Exec {
     args: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+struct ExecResponse {
+    output: String,
+}
+
 pub async fn exec_handler(id: String, body: Exec, servers: Servers) -> Result<impl Reply> {
     println!("Executed {} on {id}", body.args.iter().fold(String::new(), |s, x| format!("{s} {x}")).trim());
     if let Some(s) = servers.write().await.get(&id) {
         match s.send_command(body.args).await {
-            Ok(_) => Ok(StatusCode::OK),
+            Ok(output) => Ok(json(&ExecResponse { output })),
             Err(e) => {
                 println!("Rejection on exec: {:?}", e);
                 Err(reject::custom(e))
@@ -63,16 +178,343 @@ pub async fn exec_handler(id: String, body: Exec, servers: Servers) -> Result<im
     }
 }
 
-pub async fn full_output_handler(id: String, servers: Servers) -> Result<impl Reply> {
+pub async fn list_files_handler(id: String, query: FileQuery, servers: Servers) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    match files::list(&server, &query.path).await {
+        Ok(entries) => Ok(json(&entries)),
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn download_handler(id: String, query: FileQuery, servers: Servers) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    match files::download(&server, &query.path).await {
+        Ok((name, size, stream)) => Ok(Response::builder()
+            .header("Content-Type", "application/octet-stream")
+            .header("Content-Length", size)
+            .header("Content-Disposition", format!("attachment; filename=\"{}\"", name.replace('"', "")))
+            .body(hyper::Body::wrap_stream(stream))),
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn zip_handler(id: String, query: FileQuery, servers: Servers) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    match files::zip(&server, &query.path).await {
+        Ok((name, stream)) => Ok(Response::builder()
+            .header("Content-Type", "application/zip")
+            .header("Content-Disposition", format!("attachment; filename=\"{}\"", name.replace('"', "")))
+            .body(hyper::Body::wrap_stream(stream))),
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn upload_handler<S, B>(id: String, query: FileQuery, body: S, servers: Servers) -> Result<impl Reply>
+    where S: futures::Stream<Item = std::result::Result<B, warp::Error>>, B: hyper::body::Buf
+{
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    match files::upload(&server, &query.path, Box::pin(body)).await {
+        Ok(size) => {
+            println!("Uploaded {} ({size} bytes) to {id}", query.path);
+            Ok(StatusCode::OK)
+        },
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn delete_file_handler(id: String, query: FileQuery, servers: Servers) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    match files::delete(&server, &query.path).await {
+        Ok(_) => {
+            println!("Deleted {} from {id}", query.path);
+            Ok(StatusCode::OK)
+        },
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn rename_handler(id: String, body: Rename, servers: Servers) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    match files::rename(&server, &body).await {
+        Ok(_) => Ok(StatusCode::OK),
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn mkdir_handler(id: String, query: FileQuery, servers: Servers) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    match files::mkdir(&server, &query.path).await {
+        Ok(_) => Ok(StatusCode::OK),
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn upgrade_handler(id: String, body: Upgrade, servers: Servers, config: Config, states: States, events: Events) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    match upgrade::begin(&server, &body) {
+        Ok(status) => {
+            println!("Upgrading {id} from {} to {}", status.from, status.to);
+            tokio::spawn(upgrade::run(server, body, config, states, events));
+            Ok(warp::reply::with_status(json(&status), StatusCode::ACCEPTED))
+        },
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn upgrade_status_handler(id: String, servers: Servers) -> Result<impl Reply> {
+    if !servers.read().await.contains_key(&id) {
+        return Err(reject::custom(NotRegistered { id }));
+    }
+    Ok(json(&upgrade::status(&id)))
+}
+
+pub async fn worlds_handler(id: String, servers: Servers) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    match worlds::list(&server) {
+        Ok(w) => Ok(json(&w)),
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn world_download_handler(id: String, query: WorldQuery, servers: Servers, states: States) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    match worlds::download(&server, query.world.as_deref(), states.is_running(&server).await).await {
+        Ok((name, stream)) => Ok(Response::builder()
+            .header("Content-Type", "application/gzip")
+            .header("Content-Disposition", format!("attachment; filename=\"{name}\""))
+            .body(hyper::Body::wrap_stream(stream))),
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+/// Worlds can only be swapped out while nothing has them open. Docker is asked every time, what's
+/// tracked can be behind
+async fn check_stopped(server: &Server, states: &States) -> std::result::Result<(), Rejection> {
+    if states.inspect(server).await {
+        return Err(reject::custom(Error::from("Stop the server first")));
+    }
+    Ok(())
+}
+
+pub async fn world_upload_handler<S, B>(id: String, query: WorldQuery, body: S, servers: Servers, states: States) -> Result<impl Reply>
+    where S: futures::Stream<Item = std::result::Result<B, warp::Error>>, B: hyper::body::Buf
+{
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    check_stopped(&server, &states).await?;
+    match worlds::upload(&server, query.world.as_deref(), Box::pin(body), &states).await {
+        Ok(world) => {
+            println!("Replaced {world} on {id} with an upload");
+            Ok(StatusCode::OK)
+        },
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn world_reset_handler(id: String, body: Reset, servers: Servers, config: Config, states: States) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    check_stopped(&server, &states).await?;
+    match worlds::reset(&server, &body, &config).await {
+        Ok(archive) => {
+            println!("Reset the world of {id}, the old one is in {archive}");
+            Ok(json(&BackupResponse { archive }))
+        },
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn world_switch_handler(id: String, body: Switch, servers: Servers, states: States) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    check_stopped(&server, &states).await?;
+    match worlds::switch(&server, &body).await {
+        Ok(_) => {
+            println!("Switched {id} to {}", body.world);
+            Ok(StatusCode::OK)
+        },
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ModSearch {
+    #[serde(default)]
+    query: String,
+    limit: Option<u32>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ModVersion {
+    version: Option<String>,
+}
+
+pub async fn mod_search_handler(id: String, query: ModSearch, servers: Servers, config: Config) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    let platform = match Platform::of(&server).await {
+        Ok(p) => p,
+        Err(e) => return Err(reject::custom(e)),
+    };
+    match Modrinth::new(&config).search(&platform, &query.query, query.limit.unwrap_or(20)).await {
+        Ok(hits) => Ok(json(&hits)),
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn mods_handler(id: String, servers: Servers) -> Result<impl Reply> {
+    if let Some(s) = servers.read().await.get(&id) {
+        Ok(json(&modrinth::manifest(s)))
+    } else {
+        Err(reject::custom(NotRegistered { id }))
+    }
+}
+
+pub async fn mod_install_handler(id: String, project: String, query: ModVersion, servers: Servers, config: Config) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    match Modrinth::new(&config).install(&server, &project, query.version.as_deref()).await {
+        Ok(installed) => Ok(json(&installed)),
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn mod_update_handler(id: String, project: Option<String>, servers: Servers, config: Config) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    match Modrinth::new(&config).update(&server, project.as_deref()).await {
+        Ok(updated) => Ok(json(&updated)),
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn mod_remove_handler(id: String, project: String, servers: Servers) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    match Modrinth::remove(&server, &project) {
+        Ok(removed) => {
+            println!("Removed {} from {id}", removed.slug);
+            Ok(json(&removed))
+        },
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn get_properties_handler(id: String, servers: Servers) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    match Properties::read(&server) {
+        Ok(p) => Ok(json(&p.map())),
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn patch_properties_handler(id: String, body: std::collections::BTreeMap<String, serde_json::Value>, servers: Servers, states: States) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    match properties::patch(&server, &body, states.is_running(&server).await).await {
+        Ok(result) => {
+            println!("Changed {:?} in the properties of {id}", result.changed);
+            Ok(json(&result))
+        },
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn roster_handler(id: String, servers: Servers, sessions: Sessions) -> Result<impl Reply> {
+    let server = servers.read().await.get(&id).cloned();
+    if let Some(s) = server {
+        Ok(json(&sessions.roster(&s).await))
+    } else {
+        Err(reject::custom(NotRegistered { id }))
+    }
+}
+
+pub async fn player_list_handler(id: String, list: PlayerList, servers: Servers) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    match players::list(&server, list) {
+        Ok(entries) => Ok(json(&entries)),
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn player_add_handler(id: String, list: PlayerList, body: PlayerAction, servers: Servers, states: States) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    println!("Adding {} to {:?} on {id}", body.player, list);
+    match players::add(&server, list, &body, states.is_running(&server).await).await {
+        Ok(output) => Ok(json(&ExecResponse { output })),
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn player_remove_handler(id: String, list: PlayerList, player: String, servers: Servers, states: States) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    println!("Removing {player} from {:?} on {id}", list);
+    match players::remove(&server, list, &player, states.is_running(&server).await).await {
+        Ok(output) => Ok(json(&ExecResponse { output })),
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn kick_handler(id: String, body: PlayerAction, servers: Servers, states: States) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    if !states.is_running(&server).await {
+        return Err(reject::custom(Error::from("Server isn't running, there's no one to kick")));
+    }
+    println!("Kicking {} from {id}", body.player);
+    match players::kick(&server, &body).await {
+        Ok(output) => Ok(json(&ExecResponse { output })),
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn full_output_handler(id: String, query: LogQuery, servers: Servers, logs: Logs) -> Result<impl Reply> {
     println!("Getting output from {id}");
-    if let Some(s) = servers.write().await.get(&id) {
-        match s.output() {
+    if let Some(s) = servers.read().await.get(&id) {
+        if query.is_live() {
+            let lines = stream::iter(logs.lines(&id)).flatten().map(|l| Ok::<_, Infallible>(format!("{l}\n")));
+            return Ok(Response::new(hyper::Body::wrap_stream(lines)));
+        }
+        match s.lines(&query) {
             Ok(o) => Ok(Response::new(hyper::Body::wrap_stream(
-                        o.map(|item| 
-                            match item {
-                                Ok(out) => Ok(out.into_bytes()),
-                                Err(e) => Err(e),
-                            })))),
+                        o.map(|l| Ok::<_, Infallible>(format!("{l}\n")))))),
             Err(e) => Err(reject::custom(e))
         }
     } else {
@@ -80,10 +522,15 @@ pub async fn full_output_handler(id: String, servers: Servers) -> Result<impl Re
     }
 }
 
-pub async fn output_handler(id: String, servers: Servers) -> Result<impl Reply> {
+pub async fn output_handler(id: String, query: LogQuery, servers: Servers, logs: Logs) -> Result<impl Reply> {
     println!("Getting clean output from {id}");
-    if let Some(s) = servers.write().await.get(&id) {
-        match s.clean_output() {
+    if let Some(s) = servers.read().await.get(&id) {
+        if query.is_live() {
+            let lines = stream::iter(logs.live(&id)).flatten().filter_map(|l| future::ready(
+                events::parse(&l).and_then(|e| e.event.chat_line()).map(Ok::<_, Infallible>)));
+            return Ok(Response::new(hyper::Body::wrap_stream(lines)));
+        }
+        match s.clean_output(&query) {
             Ok(o) => Ok(Response::new(hyper::Body::wrap_stream(o))),
             Err(e) => Err(reject::custom(e))
         } 
@@ -92,6 +539,152 @@ pub async fn output_handler(id: String, servers: Servers) -> Result<impl Reply>
     }
 }
 
+pub async fn events_handler(id: String, query: LogQuery, servers: Servers, logs: Logs) -> Result<impl Reply> {
+    println!("Getting events from {id}");
+    if let Some(s) = servers.read().await.get(&id) {
+        if query.is_live() {
+            let lines = stream::iter(logs.live(&id)).flatten().filter_map(|l| future::ready(
+                events::parse(&l).map(|e| serde_json::to_string(&e).map(|j| format!("{j}\n")))));
+            return Ok(Response::new(hyper::Body::wrap_stream(lines)));
+        }
+        match s.events(&query) {
+            Ok(e) => Ok(Response::new(hyper::Body::wrap_stream(e.map(|event|
+                serde_json::to_string(&event).map(|j| format!("{j}\n")))))),
+            Err(e) => Err(reject::custom(e))
+        }
+    } else {
+        Err(reject::custom(NotRegistered { id }))
+    }
+}
+
+pub async fn log_files_handler(id: String, servers: Servers) -> Result<impl Reply> {
+    if let Some(s) = servers.read().await.get(&id) {
+        match s.log_files() {
+            Ok(f) => Ok(json(&f)),
+            Err(e) => Err(reject::custom(e))
+        }
+    } else {
+        Err(reject::custom(NotRegistered { id }))
+    }
+}
+
+pub async fn log_file_handler(id: String, file: String, servers: Servers) -> Result<impl Reply> {
+    let server = servers.read().await.get(&id).cloned();
+    if let Some(s) = server {
+        match s.read_log(&file).await {
+            Ok(text) => Ok(Response::builder().body(text)),
+            Err(e) => Err(reject::custom(e))
+        }
+    } else {
+        Err(reject::custom(NotRegistered { id }))
+    }
+}
+
+pub async fn sse_handler(id: String, last_id: Option<u64>, servers: Servers, events: Events) -> Result<impl Reply> {
+    if servers.read().await.contains_key(&id) {
+        Ok(sse_reply(events, last_id, Some(id), &[]))
+    } else {
+        Err(reject::custom(NotRegistered { id }))
+    }
+}
+
+pub async fn global_sse_handler(last_id: Option<u64>, events: Events) -> Result<impl Reply> {
+    Ok(sse_reply(events, last_id, None, &[]))
+}
+
+/// Replays whatever the client missed since `last_id` before following the live events,
+/// optionally only from one server and only the given kinds of events
+fn sse_reply(events: Events, last_id: Option<u64>, server: Option<String>, kinds: &'static [&'static str]) -> impl Reply {
+    let (missed, rx) = events.subscribe(last_id);
+    // A client that fell behind gets cut off rather than silently missing events, it reconnects
+    // with the last id it saw and gets the rest replayed
+    let live = BroadcastStream::new(rx)
+        .take_while(|e| future::ready(e.is_ok()))
+        .filter_map(|e| future::ready(e.ok()));
+
+    let stream = stream::iter(missed)
+        .chain(live)
+        .filter(move |e| future::ready(server.as_ref().map(|s| s == &e.server).unwrap_or(true)))
+        .filter(move |e| future::ready(kinds.is_empty() || kinds.contains(&e.event.event.kind())))
+        .map(|e| sse::Event::default()
+            .id(e.id.to_string())
+            .event(e.event.event.kind())
+            .json_data(&e));
+
+    sse::reply(sse::keep_alive().stream(stream))
+}
+
+pub async fn chat_stream_handler(id: String, last_id: Option<u64>, servers: Servers, events: Events) -> Result<impl Reply> {
+    if servers.read().await.contains_key(&id) {
+        Ok(sse_reply(events, last_id, Some(id), CHAT_EVENTS))
+    } else {
+        Err(reject::custom(NotRegistered { id }))
+    }
+}
+
+pub async fn chat_handler(id: String, body: ChatMessage, servers: Servers, config: Config) -> Result<impl Reply> {
+    let server = servers.read().await.get(&id).cloned();
+    if let Some(s) = server {
+        match s.send_command(body.tellraw(&config)).await {
+            Ok(_) => Ok(StatusCode::OK),
+            Err(e) => {
+                println!("Rejection on chat: {:?}", e);
+                Err(reject::custom(e))
+            }
+        }
+    } else {
+        Err(reject::custom(NotRegistered { id }))
+    }
+}
+
+pub async fn modules_handler(modules: Modules) -> Result<impl Reply> {
+    Ok(json(&modules.status()))
+}
+
+pub async fn module_self_handler(auth: String, modules: Modules) -> Result<impl Reply> {
+    let token = auth.trim_start_matches("Bearer ").trim();
+    match modules.authenticate(token) {
+        Some(m) => Ok(json(&m)),
+        None => Err(reject::custom(Error::from("Unknown module token"))),
+    }
+}
+
+/// Holds calls made with a module token to that module's scope
+pub async fn module_scope_handler(path: FullPath, auth: Option<String>, modules: Modules) -> Result<()> {
+    modules.check(auth.as_deref(), path.as_str()).map_err(|reason| reject::custom(Forbidden { reason }))
+}
+
+pub async fn deliveries_handler(webhooks: Webhooks) -> Result<impl Reply> {
+    Ok(json(&webhooks.deliveries()))
+}
+
+pub async fn crashes_handler(id: String, servers: Servers) -> Result<impl Reply> {
+    if let Some(s) = servers.read().await.get(&id) {
+        match crash::records(s) {
+            Ok(r) => Ok(json(&r)),
+            Err(e) => Err(reject::custom(e))
+        }
+    } else {
+        Err(reject::custom(NotRegistered { id }))
+    }
+}
+
+pub async fn auto_restart_handler(id: String, body: AutoRestart, servers: Servers) -> Result<impl Reply> {
+    println!("Updating auto restart for {id}");
+    if let Err(e) = body.validate() {
+        return Err(reject::custom(e));
+    }
+    if let Some(s) = servers.write().await.get_mut(&id) {
+        s.auto_restart = body;
+        if let Err(_) = s.save().await {
+            return Err(reject::custom(Error::from("Failed to save auto restart to firebase")));
+        }
+        Ok(StatusCode::OK)
+    } else {
+        Err(reject::custom(NotRegistered { id }))
+    }
+}
+
 // I'm gonna neglect this one for a second because I wanna redo it later
 pub async fn get_status(id: String, servers: Servers) -> std::result::Result<craftping::Response, Rejection> {
     println!("Attempting to get status of {id}");
@@ -110,11 +703,52 @@ pub async fn full_status_handler(_servers: Servers) -> Result<impl Reply> {
     Ok(StatusCode::OK)
 }
 
-// I actually don't know what to do here
-pub async fn partial_status_handler(id: String, servers: Servers) -> Result<impl Reply> {
-    match get_status(id, servers).await {
-        Ok(s) => Ok(json(&s)),
-        Err(e) => Err(e)
+#[derive(Serialize, Debug)]
+struct StatusResponse {
+    online: bool,
+    state: Option<ServerState>,
+    resources: Option<Resources>,
+    tick: Option<Tick>,
+    limits: Option<Limits>,
+    #[serde(flatten)]
+    ping: Option<craftping::Response>,
+}
+
+pub async fn partial_status_handler(id: String, servers: Servers, states: States, stats: Stats, ticks: Ticks) -> Result<impl Reply> {
+    if !servers.read().await.contains_key(&id) {
+        return Err(reject::custom(NotRegistered { id }));
+    }
+    let limits = servers.read().await.get(&id).and_then(|s| s.limits().ok());
+    // A server that's down still has a state worth reporting
+    let ping = get_status(id.clone(), servers).await.ok();
+    Ok(json(&StatusResponse {
+        online: ping.is_some(),
+        state: states.get(&id),
+        resources: stats.current(&id),
+        tick: ticks.current(&id),
+        limits,
+        ping,
+    }))
+}
+
+pub async fn tps_handler(id: String, servers: Servers, ticks: Ticks) -> Result<impl Reply> {
+    if servers.read().await.contains_key(&id) {
+        Ok(json(&ticks.history(&id)))
+    } else {
+        Err(reject::custom(NotRegistered { id }))
+    }
+}
+
+pub async fn metrics_handler(servers: Servers, states: States, stats: Stats, ticks: Ticks) -> Result<impl Reply> {
+    let body = crate::metrics::render(servers, states, stats, ticks).await;
+    Ok(warp::reply::with_header(body, "Content-Type", "text/plain; version=0.0.4"))
+}
+
+pub async fn stats_handler(id: String, servers: Servers, stats: Stats) -> Result<impl Reply> {
+    if servers.read().await.contains_key(&id) {
+        Ok(json(&stats.history(&id)))
+    } else {
+        Err(reject::custom(NotRegistered { id }))
     }
 }
 
@@ -125,20 +759,90 @@ pub struct New {
     port: Option<u16>,
     version: Option<String>,
     server_type: Option<String>,
+    limits: Option<Limits>,
 }
 
-pub async fn new_handler(body: New, servers: Servers, config: Config) -> Result<impl Reply> {
+pub async fn new_handler(body: New, servers: Servers, config: Config, events: Events, logs: Logs, stats: Stats, states: States) -> Result<impl Reply> {
     println!("Creating new server...");
+    if let Some(l) = &body.limits {
+        if let Err(e) = l.validate() {
+            return Err(reject::custom(e));
+        }
+    }
     let ports = servers.write().await.values().clone().map(|v| v.port).collect::<Vec<u16>>();
-    match Server::new(body.id, body.path, body.port, Some(ports), body.version, body.server_type, config).await {
+    match Server::new(body.id, body.path, body.port, Some(ports), body.version, body.server_type, body.limits, config).await {
         Ok(s) => {
-            servers.write().await.insert(s.name.clone(), s);
+            let name = s.name.clone();
+            servers.write().await.insert(name.clone(), s.clone());
+            // It started before docker events for it were looked at, so its state has to be read
+            states.inspect(&s).await;
+            crate::watch(name, servers.clone(), events, logs, stats);
             Ok(StatusCode::OK) 
         },
         Err(e) => Err(reject::custom(e)) 
     }
 }
 
+#[derive(Serialize, Debug)]
+struct LimitsResponse {
+    limits: Limits,
+    /// Some of the limits only apply once the container is recreated
+    recreate_required: bool,
+}
+
+pub async fn limits_handler(id: String, body: Limits, servers: Servers, states: States) -> Result<impl Reply> {
+    let server = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    match server.set_limits(&body, states.is_running(&server).await).await {
+        Ok(recreate_required) => {
+            println!("Updated the limits of {id}");
+            let limits = server.limits().unwrap_or(body);
+            Ok(json(&LimitsResponse { limits, recreate_required }))
+        },
+        Err(e) => Err(reject::custom(e)),
+    }
+}
+
+pub async fn clone_handler(id: String, body: CloneRequest, servers: Servers, config: Config, events: Events, logs: Logs, stats: Stats, states: States) -> Result<impl Reply> {
+    let source = if let Some(s) = servers.read().await.get(&id) { s.clone() } else {
+        return Err(reject::custom(NotRegistered { id }));
+    };
+    if servers.read().await.contains_key(&body.name) {
+        return Err(reject::custom(Error::from("A server with that name already exists")));
+    }
+    let ports = servers.read().await.values().map(|v| v.port).collect::<Vec<u16>>();
+    let port = match body.port {
+        Some(p) if ports.contains(&p) => return Err(reject::custom(Error::from("Another server already uses that port"))),
+        Some(p) => p,
+        None => match clone::free_port(&ports) {
+            Ok(p) => p,
+            Err(e) => return Err(reject::custom(e)),
+        },
+    };
+
+    println!("Cloning {id} to {}...", body.name);
+    let path = match clone::copy(&source, &body, &config).await {
+        Ok(p) => p,
+        Err(e) => return Err(reject::custom(e)),
+    };
+    // Rewrites the ports in the copied compose file and brings the clone up under its own name
+    match Server::new(body.name.clone(), Some(path.clone()), Some(port), Some(ports), None, None, None, config).await {
+        Ok(s) => {
+            let name = s.name.clone();
+            servers.write().await.insert(name.clone(), s.clone());
+            states.inspect(&s).await;
+            crate::watch(name, servers.clone(), events, logs, stats);
+            Ok(StatusCode::OK)
+        },
+        Err(e) => {
+            // Nothing is registered, so the copy would only be in the way of trying again
+            let _ = std::fs::remove_dir_all(&path);
+            Err(reject::custom(e))
+        },
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct ListResponse {
     servers: Vec<String>,
@@ -1,10 +1,14 @@
+// `if let Err(_) = ...` is how errors get turned into our own all over, same as the
+// `if let Ok(x) = ... else` blocks around them. Handlers get everything they use handed in by
+// warp, so they take as many arguments as they need
+#![allow(clippy::redundant_pattern_matching, clippy::too_many_arguments)]
+
 use std::collections::HashMap; 
 use std::sync::Arc; 
 use tokio::sync::RwLock; 
-use server::Server; 
+use server::{Server, StopConfig}; 
 use std::fs; 
 use serde::Deserialize; 
-use std::process::Command; 
 use cloudsync::CloudSync;
 
 pub mod server;
@@ -12,8 +16,53 @@ pub mod net;
 pub mod status;
 pub mod handlers;
 pub mod error;
+pub mod schedule;
+pub mod rcon;
+pub mod events;
+pub mod bus;
+pub mod logs;
+pub mod chat;
+pub mod modules;
+pub mod webhooks;
+pub mod watcher;
+pub mod crash;
+pub mod stats;
+pub mod metrics;
+pub mod tps;
+pub mod limits;
+pub mod players;
+pub mod sessions;
+pub mod properties;
+pub mod files;
+pub mod modrinth;
+pub mod worlds;
+pub mod upgrade;
+pub mod clone;
 
 pub type Servers = Arc<RwLock<HashMap<String, Server>>>;
+pub type Events = Arc<bus::EventBus>;
+pub type Logs = Arc<logs::LogHub>;
+pub type Modules = Arc<modules::Supervisor>;
+pub type Webhooks = Arc<webhooks::Dispatcher>;
+pub type States = Arc<watcher::StateTracker>;
+pub type Stats = Arc<stats::StatsCollector>;
+pub type Ticks = Arc<tps::TickMonitor>;
+pub type Sessions = Arc<sessions::SessionTracker>;
+
+/// Everything the web server hands out to its handlers
+#[derive(Clone)]
+pub struct Shared {
+    pub servers: Servers,
+    pub config: Config,
+    pub events: Events,
+    pub logs: Logs,
+    pub modules: Modules,
+    pub webhooks: Webhooks,
+    pub states: States,
+    pub stats: Stats,
+    pub ticks: Ticks,
+    pub sessions: Sessions,
+}
 
 pub async fn run() {
     // fix this
@@ -22,13 +71,41 @@ pub async fn run() {
 
         println!("Servers: {:?}", servers.write().await);
 
-        load_modules(config.clone()).await;
-        net::start_ws(servers, config).await;  
+        let events: Events = Arc::new(bus::EventBus::new());
+        let logs: Logs = Arc::new(logs::LogHub::new());
+        let stats: Stats = Arc::new(stats::StatsCollector::new());
+        for name in servers.read().await.keys() {
+            watch(name.clone(), servers.clone(), events.clone(), logs.clone(), stats.clone());
+        }
+
+        let states: States = Arc::new(watcher::StateTracker::new());
+        tokio::spawn(watcher::watch(servers.clone(), states.clone(), events.clone()));
+        tokio::spawn(crash::monitor(servers.clone(), states.clone(), logs.clone(), events.clone()));
+
+        let ticks: Ticks = Arc::new(tps::TickMonitor::new());
+        tokio::spawn(tps::poll(servers.clone(), states.clone(), ticks.clone()));
+
+        let sessions: Sessions = Arc::new(sessions::SessionTracker::new());
+        tokio::spawn(sessions::track(servers.clone(), states.clone(), events.clone(), sessions.clone()));
+
+        let modules = modules::Supervisor::start(&config);
+        let webhooks = webhooks::Dispatcher::start(&config, events.clone());
+        tokio::spawn(schedule::run_scheduler(servers.clone(), config.clone(), states.clone()));
+        net::start_ws(Shared { servers, config, events, logs, modules, webhooks, states, stats, ticks, sessions }).await;  
     } else {
         println!("Some error getting the config file from the filepath, please fix this and run mc-docker again :3");
     }
 }
 
+/// Starts the background tasks that follow a server, these stop on their own once it's removed
+pub fn watch(name: String, servers: Servers, events: Events, logs: Logs, stats: Stats) {
+    // Open before anything subscribes, so the pump doesn't miss the start of the output
+    logs.open(&name);
+    tokio::spawn(logs::follow(name.clone(), servers.clone(), logs.clone()));
+    tokio::spawn(stats::collect(name.clone(), servers.clone(), stats));
+    tokio::spawn(bus::pump(name, servers, events, logs));
+}
+
 /// Returns the list of servers from the cloud, if none are found, creates an empty
 /// list and warns the user
 pub async fn load_from_cloud() -> Servers {
@@ -55,10 +132,25 @@ pub struct Config {
     pub fb_id: String,
     pub ws_port: u16,
     pub path: String,
-    pub modules: Vec<String>,
+    #[serde(default, deserialize_with = "modules::unique_names")]
+    pub modules: Vec<modules::ModuleConfig>,
+    #[serde(default)]
+    pub stop: StopConfig,
+    pub backups: Option<String>,
+    #[serde(default)]
+    pub chat: HashMap<String, chat::ChatSource>,
+    #[serde(default)]
+    pub webhooks: Vec<webhooks::WebhookConfig>,
+    /// Where the Modrinth API lives, only worth changing to point it at a stand-in
+    pub modrinth: Option<String>,
 }
 
 impl Config {
+    /// Where server backups go, defaults to a hidden folder next to the servers
+    pub fn backup_path(&self) -> String {
+        self.backups.clone().unwrap_or(format!("{}/.backups", self.path))
+    }
+
     fn get() -> Option<Config> {
         if let Ok(file) = fs::read_to_string(format!("{CONF_PATH}/config.toml")) {
             Some(toml::from_str(&file).expect("Error parsing config file from toml"))
@@ -68,13 +160,3 @@ impl Config {
         }
     }
 }
-
-async fn load_modules(config: Config) {
-    for module in config.modules {
-        tokio::spawn(async move {
-            // change this so we can specific a module path?
-            let _m = Command::new(module)
-                .spawn().unwrap();
-        });
-    }
-}
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+use crate::error::Error;
+
+// Docker's default CFS period, cpus is turned into a quota of this
+pub const CPU_PERIOD: i64 = 100_000;
+
+/// How much of the host a server's container is allowed to use, stored in its compose file
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    /// Hard memory limit, like `4G` or `512M`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<String>,
+    /// JVM heap (the image's MEMORY), three quarters of the memory limit when left out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heap: Option<String>,
+    /// Cores the server may use, like `1.5`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<f64>,
+    /// Relative weight against the other containers when the CPU is busy, docker's default is 1024
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_shares: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pids: Option<i64>,
+}
+
+/// Bytes from a docker style size, `4G`, `512m`, `1024k` or plain bytes
+pub fn parse_memory(size: &str) -> Result<i64, Error> {
+    let size = size.trim().to_lowercase();
+    let size = size.trim_end_matches('b');
+    let (number, unit) = match size.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&size[..i], c),
+        _ => (size, ' '),
+    };
+    let multiplier: i64 = match unit {
+        ' ' => 1,
+        'k' => 1 << 10,
+        'm' => 1 << 20,
+        'g' => 1 << 30,
+        't' => 1 << 40,
+        _ => return Err(Error::from("Unknown unit in memory size")),
+    };
+    if let Ok(n) = number.trim().parse::<f64>() {
+        if n > 0.0 { return Ok((n * multiplier as f64) as i64) }
+    }
+    Err(Error::from("Memory size is not a positive number"))
+}
+
+impl Limits {
+    pub fn memory_bytes(&self) -> Result<Option<i64>, Error> {
+        self.memory.as_deref().map(parse_memory).transpose()
+    }
+
+    /// The CFS quota matching `cpus`, for a period of `CPU_PERIOD`
+    pub fn cpu_quota(&self) -> Option<i64> {
+        self.cpus.map(|c| (c * CPU_PERIOD as f64) as i64)
+    }
+
+    /// What MEMORY should be set to, so the JVM never asks for more than the container gets
+    pub fn heap(&self) -> Result<Option<String>, Error> {
+        let limit = self.memory_bytes()?;
+        match (&self.heap, limit) {
+            (Some(h), Some(l)) => {
+                // The JVM needs room for more than just the heap
+                if parse_memory(h)? >= l {
+                    return Err(Error::from("The heap has to be smaller than the memory limit"));
+                }
+                Ok(Some(h.clone()))
+            },
+            (Some(h), None) => {
+                parse_memory(h)?;
+                Ok(Some(h.clone()))
+            },
+            (None, Some(l)) => Ok(Some(format!("{}M", l * 3 / 4 / (1 << 20)))),
+            (None, None) => Ok(None),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        self.heap()?;
+        if let Some(m) = self.memory_bytes()? {
+            // Below this docker refuses to start the container at all
+            if m < 6 * (1 << 20) {
+                return Err(Error::from("Memory limit is too small"));
+            }
+        }
+        if self.cpus.map(|c| c <= 0.0).unwrap_or(false) {
+            return Err(Error::from("cpus has to be more than 0"));
+        }
+        if self.cpu_shares.map(|s| s < 2).unwrap_or(false) {
+            return Err(Error::from("cpu_shares has to be at least 2"));
+        }
+        if self.pids.map(|p| p < 1).unwrap_or(false) {
+            return Err(Error::from("pids has to be at least 1"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use chrono::Utc;
+use futures::{Stream, StreamExt, stream, future};
+use tokio::sync::broadcast;
+use tokio::time::{sleep, Duration};
+use tokio_stream::wrappers::BroadcastStream;
+use crate::Servers;
+use crate::server::LogQuery;
+
+// Lines of output kept per server for new subscribers and crash reports
+const RECENT: usize = 500;
+
+/// Shares a single docker log stream per server between everyone who wants its output
+#[derive(Default)]
+pub struct LogHub {
+    feeds: Mutex<HashMap<String, Arc<Feed>>>,
+}
+
+struct Feed {
+    tx: broadcast::Sender<String>,
+    recent: Mutex<VecDeque<String>>,
+}
+
+impl Feed {
+    fn new() -> Feed {
+        let (tx, _) = broadcast::channel(RECENT);
+        Feed { tx, recent: Mutex::new(VecDeque::with_capacity(RECENT)) }
+    }
+
+    fn push(&self, line: String) {
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT {
+            recent.pop_front();
+        }
+        recent.push_back(line.clone());
+        // Under the lock so nobody subscribing right now sees a line twice or not at all
+        let _ = self.tx.send(line);
+    }
+}
+
+impl LogHub {
+    pub fn new() -> LogHub {
+        LogHub::default()
+    }
+
+    /// Sets up the feed of a server, `follow` takes it down again once the server is removed
+    pub fn open(&self, name: &str) {
+        self.feeds.lock().unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(Feed::new()));
+    }
+
+    fn feed(&self, name: &str) -> Option<Arc<Feed>> {
+        self.feeds.lock().unwrap().get(name).cloned()
+    }
+
+    /// The most recent lines of output, followed by everything new. None when nothing follows
+    /// the server
+    pub fn lines(&self, name: &str) -> Option<impl Stream<Item = String>> {
+        let feed = self.feed(name)?;
+        let recent = feed.recent.lock().unwrap();
+        let live = BroadcastStream::new(feed.tx.subscribe()).filter_map(|l| future::ready(l.ok()));
+        Some(stream::iter(recent.iter().cloned().collect::<Vec<_>>()).chain(live))
+    }
+
+    /// Only the lines that come in from now on, None when nothing follows the server
+    pub fn live(&self, name: &str) -> Option<impl Stream<Item = String>> {
+        let feed = self.feed(name)?;
+        Some(BroadcastStream::new(feed.tx.subscribe()).filter_map(|l| future::ready(l.ok())))
+    }
+
+    pub fn recent(&self, name: &str) -> Vec<String> {
+        self.feed(name).map(|f| f.recent.lock().unwrap().iter().cloned().collect()).unwrap_or_default()
+    }
+}
+
+/// Follows the output of a server for as long as it's registered and hands it out line by line
+pub async fn follow(name: String, servers: Servers, logs: Arc<LogHub>) {
+    logs.open(&name);
+    let feed = if let Some(f) = logs.feed(&name) { f } else { return };
+    let mut since = Utc::now().timestamp();
+
+    loop {
+        let server = if let Some(s) = servers.read().await.get(&name) { s.clone() } else {
+            println!("{name} is no longer registered, no longer following its output");
+            logs.feeds.lock().unwrap().remove(&name);
+            return;
+        };
+
+        let query = LogQuery { since: Some(since.to_string()), ..Default::default() };
+        if let Ok(output) = server.output(&query) {
+            let mut lines = Box::pin(split_lines(output));
+            while let Some(line) = lines.next().await {
+                feed.push(line);
+            }
+        }
+
+        // The stream ends whenever the container stops, pick it back up where it left off
+        since = Utc::now().timestamp();
+        sleep(Duration::from_secs(5)).await;
+    }
+}
+
+/// Docker hands over whatever the tty wrote, which isn't always whole lines, so this puts the
+/// lines back together. The output stops at the first error, same as when docker closes it
+pub fn split_lines<S, T, E>(output: S) -> impl Stream<Item = String>
+    where S: Stream<Item = Result<T, E>>, T: ToString
+{
+    output
+        .map(Some)
+        .chain(stream::iter([None]))
+        .scan((String::new(), false), |(pending, done), chunk| {
+            if *done {
+                return future::ready(None);
+            }
+            let mut lines = Vec::new();
+            match chunk {
+                Some(Ok(chunk)) => pending.push_str(&chunk.to_string()),
+                _ => *done = true,
+            }
+            while let Some(i) = pending.find('\n') {
+                lines.push(pending[..i].trim_end_matches('\r').to_string());
+                pending.drain(..=i);
+            }
+            if *done && !pending.is_empty() {
+                lines.push(std::mem::take(pending));
+            }
+            future::ready(Some(lines))
+        })
+        .flat_map(stream::iter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn split(chunks: Vec<Result<&'static str, ()>>) -> Vec<String> {
+        split_lines(stream::iter(chunks)).collect().await
+    }
+
+    #[tokio::test]
+    async fn joins_chunks_into_lines() {
+        let lines = split(vec![
+            Ok("[12:00:00 INFO]: Ste"),
+            Ok("ve joined the game\r\n[12:00:01"),
+            Ok(" INFO]: <Steve> hi\n\n[12:00:02 INFO]: a\nb\n"),
+            Ok("no newline at the end"),
+        ]).await;
+        assert_eq!(lines, [
+            "[12:00:00 INFO]: Steve joined the game",
+            "[12:00:01 INFO]: <Steve> hi",
+            "",
+            "[12:00:02 INFO]: a",
+            "b",
+            "no newline at the end",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn stops_at_an_error() {
+        let lines = split(vec![Ok("one\ntw"), Err(()), Ok("o\nthree\n")]).await;
+        assert_eq!(lines, ["one", "tw"]);
+    }
+
+    #[test]
+    fn subscribing_leaves_unknown_servers_alone() {
+        let logs = LogHub::new();
+        assert!(logs.live("gone").is_none());
+        assert!(logs.lines("gone").is_none());
+        assert!(logs.recent("gone").is_empty());
+        assert!(logs.feeds.lock().unwrap().is_empty());
+
+        logs.open("here");
+        logs.feed("here").unwrap().push("hello".to_string());
+        assert!(logs.live("here").is_some());
+        assert_eq!(logs.recent("here"), ["hello"]);
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use tokio::time::timeout;
+use crate::{Servers, States, Stats, Ticks};
+
+// Upper bounds of the request latency histogram, in seconds
+const BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0];
+
+const METHODS: [&str; 7] = ["GET", "POST", "PUT", "DELETE", "PATCH", "HEAD", "OPTIONS"];
+// A status ping that takes longer than this isn't waited on for a scrape
+const PING_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Default)]
+struct Latency {
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+lazy_static! {
+    // (route, method, status) -> latency
+    static ref REQUESTS: Mutex<BTreeMap<(String, String, u16), Latency>> = Mutex::new(BTreeMap::new());
+    // (status, kind) -> count
+    static ref ERRORS: Mutex<BTreeMap<(u16, String), u64>> = Mutex::new(BTreeMap::new());
+}
+
+/// Counts a finished request, only the first segment of the path is kept so server names
+/// don't blow up the number of series
+pub fn record_request(path: &str, method: &str, status: u16, elapsed: Duration) {
+    let route = path.trim_start_matches('/').split('/').next().unwrap_or_default();
+    // Handlers never answer with a 404 themselves, so it means no route matched and the path
+    // could be anything
+    let route = if status == 404 { "other".to_string() }
+        else if route.is_empty() { "/".to_string() }
+        else { format!("/{route}") };
+    // Same for made up methods
+    let method = if METHODS.contains(&method) { method } else { "other" };
+    let secs = elapsed.as_secs_f64();
+
+    let mut requests = REQUESTS.lock().unwrap();
+    let latency = requests.entry((route, method.to_string(), status)).or_default();
+    latency.count += 1;
+    latency.sum += secs;
+    for (i, bound) in BUCKETS.iter().enumerate() {
+        if secs <= *bound {
+            latency.buckets[i] += 1;
+        }
+    }
+}
+
+/// Counts an error handed back by `handle_rejection`
+pub fn record_error(status: u16, kind: &str) {
+    *ERRORS.lock().unwrap().entry((status, kind.to_string())).or_insert(0) += 1;
+}
+
+/// Quotes a label value the way the text format wants it
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Everything in the Prometheus text format
+pub async fn render(servers: Servers, states: States, stats: Stats, ticks: Ticks) -> String {
+    let servers: Vec<_> = servers.read().await.values().cloned().collect();
+
+    // Only ping servers that are actually running, a ping to a stopped one just times out. They're
+    // all pinged at once, and one that hangs is left out rather than holding up the scrape
+    let pinging: Vec<_> = servers.iter()
+        .filter(|s| states.was_running(&s.name))
+        .cloned()
+        .map(|server| tokio::spawn(async move {
+            let start = Instant::now();
+            let ping = timeout(PING_TIMEOUT, server.status()).await;
+            (server.name, ping, start.elapsed())
+        }))
+        .collect();
+    let mut pings = HashMap::new();
+    for handle in pinging {
+        if let Ok((name, Ok(Ok(r)), elapsed)) = handle.await {
+            pings.insert(name, (r, elapsed));
+        }
+    }
+
+    let mut out = String::new();
+
+    header(&mut out, "mc_docker_server_up", "gauge", "Whether the server's container is running");
+    for server in &servers {
+        let _ = writeln!(out, "mc_docker_server_up{{server=\"{}\"}} {}", label(&server.name), states.was_running(&server.name) as u8);
+    }
+
+    header(&mut out, "mc_docker_server_online", "gauge", "Whether the server answers a status ping");
+    for server in &servers {
+        let _ = writeln!(out, "mc_docker_server_online{{server=\"{}\"}} {}", label(&server.name), pings.contains_key(&server.name) as u8);
+    }
+
+    header(&mut out, "mc_docker_players_online", "gauge", "Players on the server");
+    for (name, (r, _)) in &pings {
+        let _ = writeln!(out, "mc_docker_players_online{{server=\"{}\"}} {}", label(name), r.online_players);
+    }
+
+    header(&mut out, "mc_docker_players_max", "gauge", "Player slots on the server");
+    for (name, (r, _)) in &pings {
+        let _ = writeln!(out, "mc_docker_players_max{{server=\"{}\"}} {}", label(name), r.max_players);
+    }
+
+    header(&mut out, "mc_docker_ping_seconds", "gauge", "How long the status ping took");
+    for (name, (_, elapsed)) in &pings {
+        let _ = writeln!(out, "mc_docker_ping_seconds{{server=\"{}\"}} {}", label(name), elapsed.as_secs_f64());
+    }
+
+    let resources: Vec<_> = servers.iter().filter_map(|s| Some((s.name.clone(), stats.current(&s.name)?))).collect();
+
+    header(&mut out, "mc_docker_cpu_percent", "gauge", "CPU used by the container, 100 per core");
+    for (name, r) in &resources {
+        let _ = writeln!(out, "mc_docker_cpu_percent{{server=\"{}\"}} {}", label(name), r.cpu_percent);
+    }
+
+    header(&mut out, "mc_docker_memory_bytes", "gauge", "Memory used by the container, without page cache");
+    for (name, r) in &resources {
+        let _ = writeln!(out, "mc_docker_memory_bytes{{server=\"{}\"}} {}", label(name), r.memory_usage);
+    }
+
+    header(&mut out, "mc_docker_memory_limit_bytes", "gauge", "Memory the container is allowed to use");
+    for (name, r) in &resources {
+        let _ = writeln!(out, "mc_docker_memory_limit_bytes{{server=\"{}\"}} {}", label(name), r.memory_limit);
+    }
+
+    // The last answer of a server that went down since isn't worth reporting
+    let tps: Vec<_> = servers.iter()
+        .filter(|s| states.was_running(&s.name))
+        .filter_map(|s| Some((s.name.clone(), ticks.current(&s.name)?)))
+        .collect();
+
+    header(&mut out, "mc_docker_tps", "gauge", "Ticks per second, 20 when the server keeps up");
+    for (name, t) in &tps {
+        let _ = writeln!(out, "mc_docker_tps{{server=\"{}\"}} {}", label(name), t.tps);
+    }
+
+    header(&mut out, "mc_docker_mspt", "gauge", "Milliseconds spent on each tick");
+    for (name, t) in &tps {
+        if let Some(mspt) = t.mspt {
+            let _ = writeln!(out, "mc_docker_mspt{{server=\"{}\"}} {}", label(name), mspt);
+        }
+    }
+
+    {
+        let requests = REQUESTS.lock().unwrap();
+
+        header(&mut out, "mc_docker_http_requests_total", "counter", "Requests handled by the web server");
+        for ((route, method, status), l) in requests.iter() {
+            let _ = writeln!(out, "mc_docker_http_requests_total{{route=\"{}\",method=\"{method}\",status=\"{status}\"}} {}", label(route), l.count);
+        }
+
+        header(&mut out, "mc_docker_http_request_duration_seconds", "histogram", "How long requests took to handle");
+        for ((route, method, status), l) in requests.iter() {
+            let labels = format!("route=\"{}\",method=\"{method}\",status=\"{status}\"", label(route));
+            for (bound, count) in BUCKETS.iter().zip(l.buckets.iter()) {
+                let _ = writeln!(out, "mc_docker_http_request_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {count}");
+            }
+            let _ = writeln!(out, "mc_docker_http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}", l.count);
+            let _ = writeln!(out, "mc_docker_http_request_duration_seconds_sum{{{labels}}} {}", l.sum);
+            let _ = writeln!(out, "mc_docker_http_request_duration_seconds_count{{{labels}}} {}", l.count);
+        }
+    }
+
+    header(&mut out, "mc_docker_http_errors_total", "counter", "Errors the web server replied with");
+    for ((status, kind), count) in ERRORS.lock().unwrap().iter() {
+        let _ = writeln!(out, "mc_docker_http_errors_total{{status=\"{status}\",kind=\"{}\"}} {count}", label(kind));
+    }
+
+    out
+}
@@ -0,0 +1,613 @@
+use std::collections::VecDeque;
+use std::fs;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use ring::digest;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::time::Duration;
+use crate::Config;
+use crate::error::Error;
+use crate::server::Server;
+
+pub const DEFAULT_API: &str = "https://api.modrinth.com/v2";
+
+lazy_static! {
+    static ref GAME_VERSION: Regex = Regex::new(r"\d+\.\d+(\.\d+)?").unwrap();
+}
+
+/// Loaders the server can run, in order of preference, and where their jars go
+pub struct Platform {
+    pub loaders: Vec<&'static str>,
+    pub dir: &'static str,
+    pub game_version: String,
+}
+
+impl Platform {
+    pub async fn of(server: &Server) -> Result<Platform, Error> {
+        let (loaders, dir) = match server.server_type().as_str() {
+            "FABRIC" => (vec!["fabric"], "mods"),
+            "QUILT" => (vec!["quilt", "fabric"], "mods"),
+            "FORGE" => (vec!["forge"], "mods"),
+            "NEOFORGE" => (vec!["neoforge"], "mods"),
+            "PAPER" | "PUFFERFISH" => (vec!["paper", "spigot", "bukkit"], "plugins"),
+            "PURPUR" => (vec!["purpur", "paper", "spigot", "bukkit"], "plugins"),
+            "FOLIA" => (vec!["folia"], "plugins"),
+            "SPIGOT" => (vec!["spigot", "bukkit"], "plugins"),
+            "BUKKIT" => (vec!["bukkit"], "plugins"),
+            _ => return Err(Error::from("This server type can't load mods or plugins")),
+        };
+
+        let version = server.version()?;
+        let game_version = if version.eq_ignore_ascii_case("latest") || version.eq_ignore_ascii_case("snapshot") {
+            // Only the running server knows what LATEST turned out to be
+            let ping = if let Ok(p) = server.status().await { p } else {
+                return Err(Error::from("VERSION is LATEST, start the server once so its version is known"));
+            };
+            if let Some(v) = GAME_VERSION.find(&ping.version) { v.as_str().to_string() } else {
+                return Err(Error::from("Couldn't work out the version the server is running"));
+            }
+        } else {
+            version
+        };
+
+        Ok(Platform { loaders, dir, game_version })
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SearchHit {
+    pub project_id: String,
+    pub slug: String,
+    pub title: String,
+    pub description: String,
+    pub project_type: String,
+    pub downloads: u64,
+    pub icon_url: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchResponse {
+    hits: Vec<SearchHit>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Project {
+    id: String,
+    slug: String,
+    title: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Version {
+    id: String,
+    project_id: String,
+    version_number: String,
+    version_type: String,
+    files: Vec<VersionFile>,
+    #[serde(default)]
+    dependencies: Vec<Dependency>,
+    #[serde(default)]
+    loaders: Vec<String>,
+    #[serde(default)]
+    game_versions: Vec<String>,
+}
+
+impl Version {
+    fn runs_on(&self, platform: &Platform) -> bool {
+        self.loaders.iter().any(|l| platform.loaders.contains(&l.as_str()))
+            && self.game_versions.contains(&platform.game_version)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct VersionFile {
+    url: String,
+    filename: String,
+    primary: bool,
+    hashes: Hashes,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Hashes {
+    sha1: Option<String>,
+    sha512: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Dependency {
+    project_id: Option<String>,
+    dependency_type: String,
+}
+
+/// Something installed from Modrinth, as kept in the manifest
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Installed {
+    pub project_id: String,
+    pub slug: String,
+    pub title: String,
+    pub version_id: String,
+    pub version_number: String,
+    pub file: String,
+    pub dir: String,
+    pub sha512: Option<String>,
+    pub installed: DateTime<Utc>,
+    /// Pulled in because something else needed it
+    pub dependency: bool,
+}
+
+fn manifest_file(server: &Server) -> String {
+    format!("{}/mc-docker/modrinth.json", server.path)
+}
+
+/// Everything installed from Modrinth on a server
+pub fn manifest(server: &Server) -> Vec<Installed> {
+    fs::read_to_string(manifest_file(server)).ok()
+        .and_then(|f| serde_json::from_str(&f).ok())
+        .unwrap_or_default()
+}
+
+fn save_manifest(server: &Server, manifest: &[Installed]) -> Result<(), Error> {
+    if let Err(_) = fs::create_dir_all(format!("{}/mc-docker", server.path)) {
+        return Err(Error::from("Error creating the manifest directory"));
+    }
+    let json = if let Ok(j) = serde_json::to_string_pretty(manifest) { j } else {
+        return Err(Error::from("Error serializing the manifest"));
+    };
+    if let Err(_) = fs::write(manifest_file(server), json) {
+        return Err(Error::from("Error writing the manifest"));
+    }
+    Ok(())
+}
+
+/// Project ids and slugs end up in URLs and file names, so they're kept to what Modrinth uses
+fn check_id(id: &str) -> Result<(), Error> {
+    if id.is_empty() || id.len() > 64 || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(Error::from("Not a valid project id or slug"));
+    }
+    Ok(())
+}
+
+fn check_hashes(bytes: &[u8], hashes: &Hashes) -> Result<(), Error> {
+    let (algorithm, expected) = match (&hashes.sha512, &hashes.sha1) {
+        (Some(h), _) => (&digest::SHA512, h),
+        (None, Some(h)) => (&digest::SHA1_FOR_LEGACY_USE_ONLY, h),
+        (None, None) => return Err(Error::from("Modrinth didn't give a hash for the file")),
+    };
+    if hex::encode(digest::digest(algorithm, bytes).as_ref()) != expected.to_lowercase() {
+        return Err(Error::from("Downloaded file doesn't match its hash"));
+    }
+    Ok(())
+}
+
+/// Talks to the Modrinth API, or whatever stands in for it at `base`
+pub struct Modrinth {
+    base: String,
+    http: reqwest::Client,
+}
+
+impl Modrinth {
+    pub fn new(config: &Config) -> Modrinth {
+        Modrinth {
+            base: config.modrinth.clone().unwrap_or(DEFAULT_API.to_string()).trim_end_matches('/').to_string(),
+            // Modrinth asks for a user agent that says who's calling
+            http: reqwest::Client::builder()
+                .user_agent(concat!("mc-docker/", env!("CARGO_PKG_VERSION")))
+                .timeout(Duration::from_secs(60))
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str, query: &[(&str, String)]) -> Result<T, Error> {
+        let res = match self.http.get(format!("{}{path}", self.base)).query(query).send().await {
+            Ok(r) => r,
+            Err(_) => return Err(Error::from("Couldn't reach Modrinth")),
+        };
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(Error::from("Not found on Modrinth"));
+        }
+        if !res.status().is_success() {
+            return Err(Error::from("Modrinth returned an error"));
+        }
+        if let Ok(t) = res.json().await { Ok(t) } else {
+            Err(Error::from("Couldn't understand Modrinth's response"))
+        }
+    }
+
+    /// Projects that have a version for the server's loader and game version
+    pub async fn search(&self, platform: &Platform, query: &str, limit: u32) -> Result<Vec<SearchHit>, Error> {
+        let loaders: Vec<String> = platform.loaders.iter().map(|l| format!("categories:{l}")).collect();
+        let facets = vec![loaders, vec![format!("versions:{}", platform.game_version)]];
+        let facets = serde_json::to_string(&facets).unwrap_or_default();
+        let res: SearchResponse = self.get("/search", &[
+            ("query", query.to_string()),
+            ("facets", facets),
+            ("limit", limit.min(100).to_string()),
+        ]).await?;
+        Ok(res.hits)
+    }
+
+    /// The newest version of a project that runs on the server, releases before betas
+    async fn resolve(&self, platform: &Platform, project: &str, version: Option<&str>) -> Result<Version, Error> {
+        if let Some(v) = version {
+            check_id(v)?;
+            let v: Version = self.get(&format!("/version/{v}"), &[]).await?;
+            if !v.runs_on(platform) {
+                return Err(Error::from("That version doesn't run on the server's loader and game version"));
+            }
+            return Ok(v);
+        }
+        let loaders = serde_json::to_string(&platform.loaders).unwrap_or_default();
+        let game_versions = serde_json::to_string(&[&platform.game_version]).unwrap_or_default();
+        let versions: Vec<Version> = self.get(&format!("/project/{project}/version"), &[
+            ("loaders", loaders),
+            ("game_versions", game_versions),
+        ]).await?;
+        // Newest first already, checked again in case the API was loose with the filters
+        let versions: Vec<&Version> = versions.iter().filter(|v| v.runs_on(platform)).collect();
+        versions.iter().find(|v| v.version_type == "release").or(versions.first()).map(|v| (*v).clone())
+            .ok_or(Error::from("No version of this project runs on the server"))
+    }
+
+    async fn download(&self, server: &Server, platform: &Platform, version: &Version) -> Result<(String, Option<String>), Error> {
+        let file = if let Some(f) = version.files.iter().find(|f| f.primary).or(version.files.first()) { f } else {
+            return Err(Error::from("Version has no files"));
+        };
+        if file.filename.contains('/') || file.filename.contains('\\') || file.filename.starts_with('.') {
+            return Err(Error::from("Version has a strange file name"));
+        }
+
+        let bytes = match self.http.get(&file.url).send().await {
+            Ok(r) if r.status().is_success() => match r.bytes().await {
+                Ok(b) => b,
+                Err(_) => return Err(Error::from("Download was interrupted")),
+            },
+            _ => return Err(Error::from("Failed to download the file")),
+        };
+        check_hashes(&bytes, &file.hashes)?;
+
+        let dir = format!("{}/{}", server.path, platform.dir);
+        if let Err(_) = fs::create_dir_all(&dir) {
+            return Err(Error::from("Error creating the mods directory"));
+        }
+        let partial = format!("{dir}/.{}.download", file.filename);
+        if let Err(_) = fs::write(&partial, &bytes).and_then(|_| fs::rename(&partial, format!("{dir}/{}", file.filename))) {
+            let _ = fs::remove_file(&partial);
+            return Err(Error::from("Error writing the file"));
+        }
+        Ok((file.filename.clone(), file.hashes.sha512.clone()))
+    }
+
+    /// Installs a project along with whatever it requires, returning everything that was installed
+    pub async fn install(&self, server: &Server, project: &str, version: Option<&str>) -> Result<Vec<Installed>, Error> {
+        check_id(project)?;
+        let platform = Platform::of(server).await?;
+        let mut manifest = manifest(server);
+        let mut installed = Vec::new();
+
+        let mut queue = VecDeque::from([(project.to_string(), version.map(|v| v.to_string()), false)]);
+        while let Some((project, version, dependency)) = queue.pop_front() {
+            let info: Project = self.get(&format!("/project/{project}"), &[]).await?;
+            if manifest.iter().any(|i| i.project_id == info.id) {
+                if dependency { continue }
+                return Err(Error::from("Already installed, update it instead"));
+            }
+
+            let v = self.resolve(&platform, &info.id, version.as_deref()).await?;
+            if v.project_id != info.id {
+                return Err(Error::from("That version belongs to another project"));
+            }
+            let (file, sha512) = self.download(server, &platform, &v).await?;
+            println!("Installed {} {} on {}", info.slug, v.version_number, server.name);
+
+            for d in v.dependencies.iter().filter(|d| d.dependency_type == "required") {
+                if let Some(id) = &d.project_id {
+                    queue.push_back((id.clone(), None, true));
+                }
+            }
+
+            let entry = Installed {
+                project_id: info.id,
+                slug: info.slug,
+                title: info.title,
+                version_id: v.id,
+                version_number: v.version_number,
+                file,
+                dir: platform.dir.to_string(),
+                sha512,
+                installed: Utc::now(),
+                dependency,
+            };
+            manifest.push(entry.clone());
+            // Saved as we go, so a failing dependency doesn't lose track of what's already there
+            save_manifest(server, &manifest)?;
+            installed.push(entry);
+        }
+        Ok(installed)
+    }
+
+    /// Brings one project, or everything when left out, up to the newest version that runs on the
+    /// server. When updating everything, a project that fails is skipped rather than stopping the rest
+    pub async fn update(&self, server: &Server, project: Option<&str>) -> Result<Vec<Installed>, Error> {
+        let platform = Platform::of(server).await?;
+        let mut manifest = manifest(server);
+        let mut updated = Vec::new();
+        if let Some(p) = project {
+            if !manifest.iter().any(|e| e.project_id == p || e.slug == p) {
+                return Err(Error::from("Project isn't installed"));
+            }
+        }
+
+        for i in 0..manifest.len() {
+            let entry = manifest[i].clone();
+            if let Some(p) = project {
+                if entry.project_id != p && entry.slug != p { continue }
+            }
+            let v = match self.resolve(&platform, &entry.project_id, None).await {
+                Ok(v) => v,
+                Err(e) if project.is_some() => return Err(e),
+                Err(e) => {
+                    println!("Couldn't find an update for {} on {}: {:?}", entry.slug, server.name, e);
+                    continue;
+                },
+            };
+            if v.id == entry.version_id {
+                continue;
+            }
+
+            let (file, sha512) = match self.download(server, &platform, &v).await {
+                Ok(f) => f,
+                Err(e) if project.is_some() => return Err(e),
+                Err(e) => {
+                    println!("Couldn't update {} on {}: {:?}", entry.slug, server.name, e);
+                    continue;
+                },
+            };
+            println!("Updated {} to {} on {}", entry.slug, v.version_number, server.name);
+            manifest[i] = Installed {
+                version_id: v.id,
+                version_number: v.version_number,
+                file: file.clone(),
+                dir: platform.dir.to_string(),
+                sha512,
+                installed: Utc::now(),
+                ..entry.clone()
+            };
+            // Saved after every project, and before the old jar goes, so the manifest never points
+            // at a file that isn't there
+            save_manifest(server, &manifest)?;
+            if file != entry.file || platform.dir != entry.dir {
+                let _ = fs::remove_file(format!("{}/{}/{}", server.path, entry.dir, entry.file));
+            }
+            updated.push(manifest[i].clone());
+        }
+        Ok(updated)
+    }
+
+    /// Deletes an installed project's jar and forgets about it, dependencies are left alone
+    pub fn remove(server: &Server, project: &str) -> Result<Installed, Error> {
+        let mut manifest = manifest(server);
+        let i = if let Some(i) = manifest.iter().position(|i| i.project_id == project || i.slug == project) { i } else {
+            return Err(Error::from("Project isn't installed"));
+        };
+        let entry = manifest.remove(i);
+        let path = format!("{}/{}/{}", server.path, entry.dir, entry.file);
+        if let Err(_) = fs::remove_file(&path) {
+            println!("{path} was already gone");
+        }
+        save_manifest(server, &manifest)?;
+        Ok(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use serde_json::{json, Value};
+    use warp::{Filter, http::StatusCode, path::FullPath};
+    use super::*;
+
+    /// What the local stand-in for Modrinth answers, by path
+    #[derive(Default)]
+    struct Api {
+        json: HashMap<String, Value>,
+        files: HashMap<String, Vec<u8>>,
+    }
+
+    struct StandIn {
+        addr: SocketAddr,
+        api: Arc<Mutex<Api>>,
+        dir: PathBuf,
+        server: Server,
+    }
+
+    impl StandIn {
+        fn new(name: &str) -> StandIn {
+            let api = Arc::new(Mutex::new(Api::default()));
+            let routes = api.clone();
+            let route = warp::get().and(warp::path::full()).map(move |path: FullPath| {
+                let api = routes.lock().unwrap();
+                if let Some(j) = api.json.get(path.as_str()) {
+                    warp::reply::with_status(j.to_string().into_bytes(), StatusCode::OK)
+                } else if let Some(f) = api.files.get(path.as_str()) {
+                    warp::reply::with_status(f.clone(), StatusCode::OK)
+                } else {
+                    warp::reply::with_status(Vec::new(), StatusCode::NOT_FOUND)
+                }
+            });
+            let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+            tokio::spawn(server);
+
+            let dir = std::env::temp_dir().join(format!("mc-docker-modrinth-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("docker-compose.yml"), COMPOSE).unwrap();
+            let server: Server = serde_json::from_value(json!({
+                "name": name,
+                "id": "",
+                "path": dir.to_string_lossy(),
+                "port": 25565,
+            })).unwrap();
+            StandIn { addr, api, dir, server }
+        }
+
+        fn modrinth(&self) -> Modrinth {
+            Modrinth { base: format!("http://{}", self.addr), http: reqwest::Client::new() }
+        }
+
+        fn project(&self, id: &str, slug: &str) {
+            self.api.lock().unwrap().json.insert(format!("/project/{id}"), json!({ "id": id, "slug": slug, "title": slug }));
+            self.api.lock().unwrap().json.insert(format!("/project/{slug}"), json!({ "id": id, "slug": slug, "title": slug }));
+        }
+
+        /// A version of a project along with its jar, newest first in the project's list
+        fn version(&self, project: &str, id: &str, loader: &str, game: &str, requires: &[&str]) -> String {
+            let filename = format!("{project}-{id}.jar");
+            let contents = format!("jar of {project} {id}").into_bytes();
+            let version = json!({
+                "id": id,
+                "project_id": project,
+                "version_number": id,
+                "version_type": "release",
+                "files": [{
+                    "url": format!("http://{}/files/{filename}", self.addr),
+                    "filename": filename,
+                    "primary": true,
+                    "hashes": { "sha512": hex::encode(digest::digest(&digest::SHA512, &contents).as_ref()) },
+                }],
+                "dependencies": requires.iter().map(|r| json!({ "project_id": r, "dependency_type": "required" })).collect::<Vec<_>>(),
+                "loaders": [loader],
+                "game_versions": [game],
+            });
+
+            let mut api = self.api.lock().unwrap();
+            api.files.insert(format!("/files/{filename}"), contents);
+            api.json.insert(format!("/version/{id}"), version.clone());
+            let list = api.json.entry(format!("/project/{project}/version")).or_insert(json!([]));
+            list.as_array_mut().unwrap().insert(0, version);
+            filename
+        }
+
+        fn jar(&self, file: &str) -> PathBuf {
+            self.dir.join("mods").join(file)
+        }
+    }
+
+    impl Drop for StandIn {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    const COMPOSE: &str = r#"
+version: "3"
+services:
+  mc:
+    image: itzg/minecraft-server
+    ports: ["25565:25565"]
+    environment:
+      EULA: "TRUE"
+      VERSION: "1.20.1"
+      TYPE: FABRIC
+    tty: true
+    stdin_open: true
+    restart: unless-stopped
+    volumes: ["./:/data"]
+"#;
+
+    #[tokio::test]
+    async fn installs_with_dependencies() {
+        let stand_in = StandIn::new("install");
+        stand_in.project("AAAA", "sodium");
+        stand_in.project("BBBB", "fabric-api");
+        let sodium = stand_in.version("AAAA", "a1", "fabric", "1.20.1", &["BBBB"]);
+        let api = stand_in.version("BBBB", "b1", "fabric", "1.20.1", &[]);
+
+        let installed = stand_in.modrinth().install(&stand_in.server, "sodium", None).await.unwrap();
+        let summary: Vec<_> = installed.iter().map(|i| (i.slug.as_str(), i.file.as_str(), i.dependency)).collect();
+        assert_eq!(summary, [("sodium", sodium.as_str(), false), ("fabric-api", api.as_str(), true)]);
+        assert!(stand_in.jar(&sodium).is_file());
+        assert!(stand_in.jar(&api).is_file());
+        assert_eq!(manifest(&stand_in.server).len(), 2);
+
+        assert!(stand_in.modrinth().install(&stand_in.server, "sodium", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn picks_a_version_that_runs() {
+        let stand_in = StandIn::new("pick");
+        stand_in.project("AAAA", "sodium");
+        let fits = stand_in.version("AAAA", "a1", "fabric", "1.20.1", &[]);
+        // Newer, but the stand-in hands the list back without filtering, like an API that ignored them
+        stand_in.version("AAAA", "a2", "forge", "1.20.1", &[]);
+
+        let installed = stand_in.modrinth().install(&stand_in.server, "sodium", None).await.unwrap();
+        assert_eq!(installed[0].file, fits);
+    }
+
+    #[tokio::test]
+    async fn checks_explicit_versions() {
+        let stand_in = StandIn::new("explicit");
+        stand_in.project("AAAA", "sodium");
+        stand_in.version("AAAA", "forge1", "forge", "1.20.1", &[]);
+        stand_in.version("AAAA", "old1", "fabric", "1.19.4", &[]);
+        let fits = stand_in.version("AAAA", "fits1", "fabric", "1.20.1", &[]);
+        stand_in.project("CCCC", "other");
+        stand_in.version("CCCC", "c1", "fabric", "1.20.1", &[]);
+
+        let modrinth = stand_in.modrinth();
+        assert!(modrinth.install(&stand_in.server, "sodium", Some("forge1")).await.is_err());
+        assert!(modrinth.install(&stand_in.server, "sodium", Some("old1")).await.is_err());
+        assert!(modrinth.install(&stand_in.server, "sodium", Some("c1")).await.is_err());
+        assert!(manifest(&stand_in.server).is_empty());
+
+        let installed = modrinth.install(&stand_in.server, "sodium", Some("fits1")).await.unwrap();
+        assert_eq!(installed[0].file, fits);
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_hashes() {
+        let stand_in = StandIn::new("hash");
+        stand_in.project("AAAA", "sodium");
+        let jar = stand_in.version("AAAA", "a1", "fabric", "1.20.1", &[]);
+        stand_in.api.lock().unwrap().files.insert(format!("/files/{jar}"), b"something else".to_vec());
+
+        assert!(stand_in.modrinth().install(&stand_in.server, "sodium", None).await.is_err());
+        assert!(!stand_in.jar(&jar).exists());
+        assert!(manifest(&stand_in.server).is_empty());
+    }
+
+    #[tokio::test]
+    async fn update_keeps_the_manifest_in_step() {
+        let stand_in = StandIn::new("update");
+        stand_in.project("AAAA", "sodium");
+        stand_in.project("BBBB", "lithium");
+        let old_a = stand_in.version("AAAA", "a1", "fabric", "1.20.1", &[]);
+        let old_b = stand_in.version("BBBB", "b1", "fabric", "1.20.1", &[]);
+        let modrinth = stand_in.modrinth();
+        modrinth.install(&stand_in.server, "sodium", None).await.unwrap();
+        modrinth.install(&stand_in.server, "lithium", None).await.unwrap();
+
+        let new_a = stand_in.version("AAAA", "a2", "fabric", "1.20.1", &[]);
+        let new_b = stand_in.version("BBBB", "b2", "fabric", "1.20.1", &[]);
+        // The new lithium jar can't be downloaded
+        stand_in.api.lock().unwrap().files.remove(&format!("/files/{new_b}"));
+
+        assert!(modrinth.update(&stand_in.server, Some("lithium")).await.is_err());
+        // A typo isn't an update with nothing to do
+        assert!(modrinth.update(&stand_in.server, Some("lithum")).await.is_err());
+
+        let updated = modrinth.update(&stand_in.server, None).await.unwrap();
+        assert_eq!(updated.iter().map(|u| u.file.as_str()).collect::<Vec<_>>(), [new_a.as_str()]);
+        assert!(!stand_in.jar(&old_a).exists());
+        assert!(stand_in.jar(&old_b).is_file());
+        for entry in manifest(&stand_in.server) {
+            assert!(stand_in.jar(&entry.file).is_file(), "{} is missing", entry.file);
+        }
+        assert_eq!(manifest(&stand_in.server).iter().map(|e| e.version_id.as_str()).collect::<Vec<_>>(), ["a2", "b1"]);
+    }
+}
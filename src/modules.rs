@@ -0,0 +1,360 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tokio::time::{sleep, Duration, Instant};
+use crate::Config;
+
+// Restarts back off from one second up to this, and go back to one after running this long
+const MAX_BACKOFF: u64 = 60;
+// Routes that aren't about a single server even though something comes after them
+const GLOBAL: [&str; 2] = ["modules", "webhooks"];
+
+/// An external program started alongside mc-docker, either just a path or a full table
+#[derive(Deserialize, Debug, Clone)]
+#[serde(from = "ModuleEntry")]
+pub struct ModuleConfig {
+    pub name: String,
+    pub path: String,
+    pub args: Vec<String>,
+    pub env: HashMap<String, String>,
+    pub restart: RestartPolicy,
+    pub scope: Scope,
+}
+
+/// What a module may do with its token, anything left out isn't limited
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Scope {
+    /// Servers it may call routes for, it's kept away from routes about every server when set
+    pub servers: Option<Vec<String>>,
+    /// Routes it may call, by their first part, e.g. "exec" or "players"
+    pub actions: Option<Vec<String>>,
+}
+
+impl Scope {
+    /// Whether a call to `path` stays inside the scope
+    pub fn allows(&self, path: &str) -> bool {
+        let path = path.trim_matches('/');
+        // A module can always ask about itself
+        if path == "modules/self" {
+            return true;
+        }
+        let mut parts = path.split('/');
+        let action = parts.next().unwrap_or_default();
+        if let Some(actions) = &self.actions {
+            if !actions.iter().any(|a| a == action) {
+                return false;
+            }
+        }
+        match &self.servers {
+            None => true,
+            Some(_) if GLOBAL.contains(&action) => false,
+            // The server is always right after the route, the ones without one are about all of them
+            Some(servers) => parts.next().map(|s| servers.iter().any(|n| n == s)).unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    Never,
+    #[default]
+    OnFailure,
+    Always,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ModuleEntry {
+    Path(String),
+    Full {
+        name: Option<String>,
+        path: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        env: HashMap<String, String>,
+        #[serde(default)]
+        restart: RestartPolicy,
+        #[serde(default)]
+        scope: Scope,
+    },
+}
+
+impl From<ModuleEntry> for ModuleConfig {
+    fn from(entry: ModuleEntry) -> ModuleConfig {
+        let (name, path, args, env, restart, scope) = match entry {
+            ModuleEntry::Path(path) => (None, path, Vec::new(), HashMap::new(), RestartPolicy::default(), Scope::default()),
+            ModuleEntry::Full { name, path, args, env, restart, scope } => (name, path, args, env, restart, scope),
+        };
+        let name = name.unwrap_or(
+            Path::new(&path).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or(path.clone())
+        );
+        ModuleConfig { name, path, args, env, restart, scope }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModuleState {
+    Starting,
+    Running,
+    Restarting,
+    Exited,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ModuleStatus {
+    pub name: String,
+    pub path: String,
+    pub state: ModuleState,
+    pub pid: Option<u32>,
+    pub restarts: u32,
+    pub last_exit: Option<i32>,
+    pub started: Option<DateTime<Utc>>,
+    pub scope: Scope,
+}
+
+/// Modules are told apart by name, so two with the same one would overwrite each other's status
+pub fn unique_names<'de, D>(deserializer: D) -> Result<Vec<ModuleConfig>, D::Error>
+    where D: Deserializer<'de>
+{
+    let modules = Vec::<ModuleConfig>::deserialize(deserializer)?;
+    let mut names = HashSet::new();
+    for module in modules.iter() {
+        if !names.insert(module.name.as_str()) {
+            return Err(de::Error::custom(format!(
+                "there's more than one module named {}, give them different names", module.name
+            )));
+        }
+    }
+    Ok(modules)
+}
+
+/// Keeps the configured modules running and keeps track of how they're doing
+pub struct Supervisor {
+    status: Mutex<HashMap<String, ModuleStatus>>,
+    // token -> module name, handed to each module in MC_DOCKER_TOKEN. Calls made with it are held
+    // to the module's scope
+    tokens: HashMap<String, String>,
+}
+
+impl Supervisor {
+    /// Starts every module in the config, each one supervised by its own task
+    pub fn start(config: &Config) -> Arc<Supervisor> {
+        let mut status = HashMap::new();
+        let mut tokens = HashMap::new();
+        let mut modules = Vec::new();
+
+        for module in config.modules.iter() {
+            let token: String = rand::thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect();
+            status.insert(module.name.clone(), ModuleStatus {
+                name: module.name.clone(),
+                path: module.path.clone(),
+                state: ModuleState::Starting,
+                pid: None,
+                restarts: 0,
+                last_exit: None,
+                started: None,
+                scope: module.scope.clone(),
+            });
+            tokens.insert(token.clone(), module.name.clone());
+            modules.push((module.clone(), token));
+        }
+
+        let supervisor = Arc::new(Supervisor { status: Mutex::new(status), tokens });
+        let api = format!("http://127.0.0.1:{}", config.ws_port);
+        for (module, token) in modules {
+            tokio::spawn(supervise(module, token, api.clone(), supervisor.clone()));
+        }
+        supervisor
+    }
+
+    pub fn status(&self) -> Vec<ModuleStatus> {
+        let mut status: Vec<ModuleStatus> = self.status.lock().unwrap().values().cloned().collect();
+        status.sort_by(|a, b| a.name.cmp(&b.name));
+        status
+    }
+
+    /// The module a token was handed to, if any
+    pub fn authenticate(&self, token: &str) -> Option<ModuleStatus> {
+        let name = self.tokens.get(token)?;
+        self.status.lock().unwrap().get(name).cloned()
+    }
+
+    /// Checks a call made with a module token stays inside that module's scope. Calls without a
+    /// token aren't from a module and the API has no auth of its own, so those are let through
+    pub fn check(&self, auth: Option<&str>, path: &str) -> Result<(), String> {
+        let token = match auth.and_then(|a| a.strip_prefix("Bearer ")) {
+            Some(t) => t.trim(),
+            None => return Ok(()),
+        };
+        match self.authenticate(token) {
+            Some(m) if m.scope.allows(path) => Ok(()),
+            Some(m) => Err(format!("Module {} isn't allowed to call {path}", m.name)),
+            None => Err("Unknown module token".to_string()),
+        }
+    }
+
+    fn update(&self, name: &str, f: impl FnOnce(&mut ModuleStatus)) {
+        if let Some(s) = self.status.lock().unwrap().get_mut(name) {
+            f(s);
+        }
+    }
+}
+
+async fn supervise(module: ModuleConfig, token: String, api: String, supervisor: Arc<Supervisor>) {
+    let mut backoff = 1;
+
+    loop {
+        let started = Instant::now();
+        let child = Command::new(&module.path)
+            .args(&module.args)
+            .envs(&module.env)
+            .env("MC_DOCKER_API", &api)
+            .env("MC_DOCKER_TOKEN", &token)
+            .env("MC_DOCKER_MODULE", &module.name)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn();
+
+        let exit = match child {
+            Ok(mut c) => {
+                println!("Started module {} ({:?})", module.name, c.id());
+                supervisor.update(&module.name, |s| {
+                    s.state = ModuleState::Running;
+                    s.pid = c.id();
+                    s.started = Some(Utc::now());
+                });
+                if let Some(out) = c.stdout.take() {
+                    tokio::spawn(forward(module.name.clone(), out));
+                }
+                if let Some(err) = c.stderr.take() {
+                    tokio::spawn(forward(module.name.clone(), err));
+                }
+                c.wait().await.ok()
+            },
+            Err(e) => {
+                println!("Failed to start module {}: {e}", module.name);
+                None
+            },
+        };
+
+        let success = exit.map(|e| e.success()).unwrap_or(false);
+        let code = exit.and_then(|e| e.code());
+        println!("Module {} exited with {:?}", module.name, code);
+        supervisor.update(&module.name, |s| {
+            s.state = ModuleState::Exited;
+            s.pid = None;
+            s.last_exit = code;
+        });
+
+        match module.restart {
+            RestartPolicy::Never => return,
+            RestartPolicy::OnFailure if success => return,
+            _ => {},
+        }
+
+        if started.elapsed() > Duration::from_secs(MAX_BACKOFF) {
+            backoff = 1;
+        }
+        println!("Restarting module {} in {backoff}s", module.name);
+        supervisor.update(&module.name, |s| {
+            s.state = ModuleState::Restarting;
+            s.restarts += 1;
+        });
+        sleep(Duration::from_secs(backoff)).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Prints the output of a module into our own output, tagged with the module name
+async fn forward(name: String, out: impl AsyncRead + Unpin) {
+    let mut lines = BufReader::new(out).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        println!("[{name}] {line}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(servers: Option<&[&str]>, actions: Option<&[&str]>) -> Scope {
+        let list = |l: &[&str]| l.iter().map(|s| s.to_string()).collect();
+        Scope { servers: servers.map(list), actions: actions.map(list) }
+    }
+
+    #[test]
+    fn unlimited_by_default() {
+        let scope = Scope::default();
+        for path in ["/exec/survival", "/new", "/list", "/sse", "/modules"] {
+            assert!(scope.allows(path), "{path}");
+        }
+    }
+
+    #[test]
+    fn limited_to_servers() {
+        let scope = scope(Some(&["survival"]), None);
+        for (path, allowed) in [
+            ("/exec/survival", true),
+            ("/players/survival/whitelist/Steve", true),
+            ("/sse/survival", true),
+            ("/exec/creative", false),
+            ("/sse", false),
+            ("/new", false),
+            ("/list", false),
+            ("/modules", false),
+            ("/webhooks/deliveries", false),
+            ("/modules/self", true),
+        ] {
+            assert_eq!(scope.allows(path), allowed, "{path}");
+        }
+    }
+
+    #[test]
+    fn limited_to_actions() {
+        let scope = scope(Some(&["survival"]), Some(&["exec", "players"]));
+        for (path, allowed) in [
+            ("/exec/survival", true),
+            ("/players/survival", true),
+            ("/stop/survival", false),
+            ("/files/survival", false),
+            ("/exec/creative", false),
+            ("/modules/self", true),
+        ] {
+            assert_eq!(scope.allows(path), allowed, "{path}");
+        }
+    }
+
+    #[test]
+    fn checks_tokens() {
+        let status = ModuleStatus {
+            name: "discord".to_string(),
+            path: "/opt/discord".to_string(),
+            state: ModuleState::Running,
+            pid: None,
+            restarts: 0,
+            last_exit: None,
+            started: None,
+            scope: scope(Some(&["survival"]), None),
+        };
+        let supervisor = Supervisor {
+            status: Mutex::new(HashMap::from([("discord".to_string(), status)])),
+            tokens: HashMap::from([("secret".to_string(), "discord".to_string())]),
+        };
+
+        assert!(supervisor.check(None, "/stop/creative").is_ok());
+        assert!(supervisor.check(Some("Basic abc"), "/stop/creative").is_ok());
+        assert!(supervisor.check(Some("Bearer secret"), "/stop/survival").is_ok());
+        assert!(supervisor.check(Some("Bearer secret"), "/stop/creative").is_err());
+        assert!(supervisor.check(Some("Bearer guess"), "/stop/survival").is_err());
+    }
+}
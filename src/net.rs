@@ -1,11 +1,16 @@
 use std::convert::Infallible;
-use warp::Filter;
-use crate::{Servers, Config};
+use warp::{Filter, Rejection};
+use crate::{Shared, Modules};
 use crate::handlers::*;
 use crate::error::handle_rejection;
+use crate::server::LogQuery;
+use crate::players::PlayerList;
+use crate::files::FileQuery;
+use crate::worlds::WorldQuery;
 
 // I wonder if theres anything I can do here the help the compile time of these.
-pub async fn start_ws(servers: Servers, config: Config) {
+pub async fn start_ws(shared: Shared) {
+    let Shared { servers, config, events, logs, modules, webhooks, states, stats, ticks, sessions } = shared;
 
     // Ping the server
     // /beep`
@@ -20,6 +25,210 @@ pub async fn start_ws(servers: Servers, config: Config) {
         .and(with(servers.clone()))
         .and_then(exec_handler);
 
+    // Manage the files in a server's directory, every path is relative to it
+    // /files/{name}{ ,/download,/zip,/rename,/mkdir }?path=
+    let list_files_route = warp::path!("files" / String)
+        .and(warp::get())
+        .and(warp::query::<FileQuery>())
+        .and(with(servers.clone()))
+        .and_then(list_files_handler);
+
+    let download_route = warp::path!("files" / String / "download")
+        .and(warp::get())
+        .and(warp::query::<FileQuery>())
+        .and(with(servers.clone()))
+        .and_then(download_handler);
+
+    let zip_route = warp::path!("files" / String / "zip")
+        .and(warp::get())
+        .and(warp::query::<FileQuery>())
+        .and(with(servers.clone()))
+        .and_then(zip_handler);
+
+    let upload_route = warp::path!("files" / String)
+        .and(warp::put())
+        .and(warp::query::<FileQuery>())
+        .and(warp::body::stream())
+        .and(with(servers.clone()))
+        .and_then(upload_handler);
+
+    let delete_file_route = warp::path!("files" / String)
+        .and(warp::delete())
+        .and(warp::query::<FileQuery>())
+        .and(with(servers.clone()))
+        .and_then(delete_file_handler);
+
+    let rename_route = warp::path!("files" / String / "rename")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with(servers.clone()))
+        .and_then(rename_handler);
+
+    let mkdir_route = warp::path!("files" / String / "mkdir")
+        .and(warp::post())
+        .and(warp::query::<FileQuery>())
+        .and(with(servers.clone()))
+        .and_then(mkdir_handler);
+
+    // Copy a server into a new one, from how it is now or from a backup
+    // /clone/{name} + json
+    let clone_route = warp::path!("clone" / String)
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with(servers.clone()))
+        .and(with(config.clone()))
+        .and(with(events.clone()))
+        .and(with(logs.clone()))
+        .and(with(stats.clone()))
+        .and(with(states.clone()))
+        .and_then(clone_handler);
+
+    // Move a server to another version, rolling back if it doesn't start, and follow along
+    // /upgrade/{name} (+ json)
+    let upgrade_route = warp::path!("upgrade" / String)
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with(servers.clone()))
+        .and(with(config.clone()))
+        .and(with(states.clone()))
+        .and(with(events.clone()))
+        .and_then(upgrade_handler);
+
+    let upgrade_status_route = warp::path!("upgrade" / String)
+        .and(warp::get())
+        .and(with(servers.clone()))
+        .and_then(upgrade_status_handler);
+
+    // The worlds of a server, which ones there are and which is loaded
+    // /worlds/{name}{ ,/download?world=,/upload?world=,/reset,/current }
+    let worlds_route = warp::path!("worlds" / String)
+        .and(warp::get())
+        .and(with(servers.clone()))
+        .and_then(worlds_handler);
+
+    let world_download_route = warp::path!("worlds" / String / "download")
+        .and(warp::get())
+        .and(warp::query::<WorldQuery>())
+        .and(with(servers.clone()))
+        .and(with(states.clone()))
+        .and_then(world_download_handler);
+
+    let world_upload_route = warp::path!("worlds" / String / "upload")
+        .and(warp::put())
+        .and(warp::query::<WorldQuery>())
+        .and(warp::body::stream())
+        .and(with(servers.clone()))
+        .and(with(states.clone()))
+        .and_then(world_upload_handler);
+
+    let world_reset_route = warp::path!("worlds" / String / "reset")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with(servers.clone()))
+        .and(with(config.clone()))
+        .and(with(states.clone()))
+        .and_then(world_reset_handler);
+
+    let world_switch_route = warp::path!("worlds" / String / "current")
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(with(servers.clone()))
+        .and(with(states.clone()))
+        .and_then(world_switch_handler);
+
+    // Mods and plugins from Modrinth
+    // /mods/{name}{ ,/search?query=,/update,/{project}{ ,/update} }
+    let mod_search_route = warp::path!("mods" / String / "search")
+        .and(warp::get())
+        .and(warp::query::<ModSearch>())
+        .and(with(servers.clone()))
+        .and(with(config.clone()))
+        .and_then(mod_search_handler);
+
+    let mods_route = warp::path!("mods" / String)
+        .and(warp::get())
+        .and(with(servers.clone()))
+        .and_then(mods_handler);
+
+    let mod_update_all_route = warp::path!("mods" / String / "update")
+        .and(warp::post())
+        .map(|id| (id, None))
+        .untuple_one()
+        .and(with(servers.clone()))
+        .and(with(config.clone()))
+        .and_then(mod_update_handler);
+
+    let mod_update_route = warp::path!("mods" / String / String / "update")
+        .and(warp::post())
+        .map(|id, project| (id, Some(project)))
+        .untuple_one()
+        .and(with(servers.clone()))
+        .and(with(config.clone()))
+        .and_then(mod_update_handler);
+
+    let mod_install_route = warp::path!("mods" / String / String)
+        .and(warp::put())
+        .and(warp::query::<ModVersion>())
+        .and(with(servers.clone()))
+        .and(with(config.clone()))
+        .and_then(mod_install_handler);
+
+    let mod_remove_route = warp::path!("mods" / String / String)
+        .and(warp::delete())
+        .and(with(servers.clone()))
+        .and_then(mod_remove_handler);
+
+    // Read or change server.properties
+    // /properties/{name} (+ json)
+    let get_properties_route = warp::path!("properties" / String)
+        .and(warp::get())
+        .and(with(servers.clone()))
+        .and_then(get_properties_handler);
+
+    let patch_properties_route = warp::path!("properties" / String)
+        .and(warp::patch())
+        .and(warp::body::json())
+        .and(with(servers.clone()))
+        .and(with(states.clone()))
+        .and_then(patch_properties_handler);
+
+    // Who's online and everyone who has played on a server
+    // /players/{name}
+    let roster_route = warp::path!("players" / String)
+        .and(warp::get())
+        .and(with(servers.clone()))
+        .and(with(sessions.clone()))
+        .and_then(roster_handler);
+
+    // Whitelist, ops, bans and IP bans of a server
+    // /players/{name}/{whitelist, ops, bans, ipbans}{ ,/{player} } (+ json)
+    let player_list_route = warp::path!("players" / String / PlayerList)
+        .and(warp::get())
+        .and(with(servers.clone()))
+        .and_then(player_list_handler);
+
+    let player_add_route = warp::path!("players" / String / PlayerList)
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(with(servers.clone()))
+        .and(with(states.clone()))
+        .and_then(player_add_handler);
+
+    let player_remove_route = warp::path!("players" / String / PlayerList / String)
+        .and(warp::delete())
+        .and(with(servers.clone()))
+        .and(with(states.clone()))
+        .and_then(player_remove_handler);
+
+    // Kick someone off a server
+    // /kick/{name} + json
+    let kick_route = warp::path!("kick" / String)
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with(servers.clone()))
+        .and(with(states.clone()))
+        .and_then(kick_handler);
+
     // Start a server
     // /start/{name}
     let start_route = warp::path!("start" / String)
@@ -28,17 +237,33 @@ pub async fn start_ws(servers: Servers, config: Config) {
         .and_then(start_handler);
 
     // Stop a server
-    // /stop/{name}
+    // /stop/{name}?mode={graceful, immediate}
     let stop_route = warp::path!("stop" / String)
         .and(warp::put())
+        .and(warp::query::<StopQuery>())
         .and(with(servers.clone()))
+        .and(with(config.clone()))
+        .and(with(events.clone()))
+        .and(with(states.clone()))
         .and_then(stop_handler);
 
+    // Restart a server
+    // /restart/{name}
+    let restart_route = warp::path!("restart" / String)
+        .and(warp::put())
+        .and(with(servers.clone()))
+        .and(with(config.clone()))
+        .and(with(events.clone()))
+        .and(with(states.clone()))
+        .and_then(restart_handler);
+
     // Get the full output of a server
-    // /fullout/{name}
+    // /fullout/{name}?since=&until=&tail=&follow=&stderr=&timestamps=
     let full_output_route = warp::path!("fullout" / String)
         .and(warp::get())
+        .and(warp::query::<LogQuery>())
         .and(with(servers.clone()))
+        .and(with(logs.clone()))
         .and_then(full_output_handler);
 
     // Create a new server
@@ -48,11 +273,41 @@ pub async fn start_ws(servers: Servers, config: Config) {
         .and(warp::body::json())
         .and(with(servers.clone()))
         .and(with(config.clone()))
+        .and(with(events.clone()))
+        .and(with(logs.clone()))
+        .and(with(stats.clone()))
+        .and(with(states.clone()))
         .and_then(new_handler);
 
     // Create a backup of a server
-    //let backup_route = warp::path("backup")
-    // /backup/{id}/{_, /region/{x-y}}
+    // /backup/{name}
+    let backup_route = warp::path!("backup" / String)
+        .and(warp::put())
+        .and(with(servers.clone()))
+        .and(with(config.clone()))
+        .and_then(backup_handler);
+
+    // Change how much of the host a server may use
+    // /limits/{name} + json
+    let limits_route = warp::path!("limits" / String)
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(with(servers.clone()))
+        .and(with(states.clone()))
+        .and_then(limits_handler);
+
+    // Get or replace the scheduled tasks of a server
+    // /schedule/{name} (+ json)
+    let get_schedule_route = warp::path!("schedule" / String)
+        .and(warp::get())
+        .and(with(servers.clone()))
+        .and_then(get_schedule_handler);
+
+    let set_schedule_route = warp::path!("schedule" / String)
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(with(servers.clone()))
+        .and_then(set_schedule_handler);
 
     // Get the status of mc-docker
     // /status{ ,/{name} }
@@ -64,8 +319,37 @@ pub async fn start_ws(servers: Servers, config: Config) {
     let partial_route = warp::path("status")
         .and(warp::path::param())
         .and(with(servers.clone()))
+        .and(with(states.clone()))
+        .and(with(stats.clone()))
+        .and(with(ticks.clone()))
         .and_then(partial_status_handler);
+
+    // How fast a server has been ticking over the last hour
+    // /status/{name}/tps
+    let tps_route = warp::path!("status" / String / "tps")
+        .and(warp::get())
+        .and(with(servers.clone()))
+        .and(with(ticks.clone()))
+        .and_then(tps_handler);
     
+    // Rolling history of the resources a server used
+    // /stats/{name}
+    let stats_route = warp::path!("stats" / String)
+        .and(warp::get())
+        .and(with(servers.clone()))
+        .and(with(stats.clone()))
+        .and_then(stats_handler);
+
+    // Everything worth graphing, for Prometheus to scrape
+    // /metrics
+    let metrics_route = warp::path!("metrics")
+        .and(warp::get())
+        .and(with(servers.clone()))
+        .and(with(states.clone()))
+        .and(with(stats.clone()))
+        .and(with(ticks.clone()))
+        .and_then(metrics_handler);
+
     // List all of the servers
     // /list
     let list_route = warp::path!("list")
@@ -81,31 +365,191 @@ pub async fn start_ws(servers: Servers, config: Config) {
         .and_then(rm_handler);
 
     // Gets a cleaned output from the server
-    // /out/{name}
+    // /out/{name}?since=&until=&tail=&follow=
     let output_route = warp::path!("out" / String)
         .and(warp::get())
+        .and(warp::query::<LogQuery>())
         .and(with(servers.clone()))
+        .and(with(logs.clone()))
         .and_then(output_handler);
 
-    let routes = beep_route
+    // Stream parsed events from the server as lines of json
+    // /events/{name}?since=&until=&tail=&follow=
+    let events_route = warp::path!("events" / String)
+        .and(warp::get())
+        .and(warp::query::<LogQuery>())
+        .and(with(servers.clone()))
+        .and(with(logs.clone()))
+        .and_then(events_handler);
+
+    // List or read the log files the server rotated out
+    // /logs/{name}{ ,/{file} }
+    let log_files_route = warp::path!("logs" / String)
+        .and(warp::get())
+        .and(with(servers.clone()))
+        .and_then(log_files_handler);
+
+    let log_file_route = warp::path!("logs" / String / String)
+        .and(warp::get())
+        .and(with(servers.clone()))
+        .and_then(log_file_handler);
+
+    // Server-Sent Events for a single server or every server
+    // /sse{ ,/{name} }
+    let global_sse_route = warp::path!("sse")
+        .and(warp::get())
+        .and(warp::header::optional::<u64>("last-event-id"))
+        .and(with(events.clone()))
+        .and_then(global_sse_handler);
+
+    let sse_route = warp::path!("sse" / String)
+        .and(warp::get())
+        .and(warp::header::optional::<u64>("last-event-id"))
+        .and(with(servers.clone()))
+        .and(with(events.clone()))
+        .and_then(sse_handler);
+
+    // Relay chat in and out of a server
+    // /chat/{name} (+ json)
+    let chat_stream_route = warp::path!("chat" / String)
+        .and(warp::get())
+        .and(warp::header::optional::<u64>("last-event-id"))
+        .and(with(servers.clone()))
+        .and(with(events.clone()))
+        .and_then(chat_stream_handler);
+
+    let chat_route = warp::path!("chat" / String)
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with(servers.clone()))
+        .and(with(config.clone()))
+        .and_then(chat_handler);
+
+    // Status of the modules, or of the module calling with its token
+    // /modules{ ,/self}
+    let modules_route = warp::path!("modules")
+        .and(warp::get())
+        .and(with(modules.clone()))
+        .and_then(modules_handler);
+
+    let module_self_route = warp::path!("modules" / "self")
+        .and(warp::get())
+        .and(warp::header::<String>("authorization"))
+        .and(with(modules.clone()))
+        .and_then(module_self_handler);
+
+    // Recent webhook deliveries
+    // /webhooks/deliveries
+    let deliveries_route = warp::path!("webhooks" / "deliveries")
+        .and(warp::get())
+        .and(with(webhooks.clone()))
+        .and_then(deliveries_handler);
+
+    // Crashes recorded for a server
+    // /crashes/{name}
+    let crashes_route = warp::path!("crashes" / String)
+        .and(warp::get())
+        .and(with(servers.clone()))
+        .and_then(crashes_handler);
+
+    // Set whether a server is restarted after it crashes
+    // /autorestart/{name} + json
+    let auto_restart_route = warp::path!("autorestart" / String)
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(with(servers.clone()))
+        .and_then(auto_restart_handler);
+
+    // Boxed in groups, one long chain of `or`s nests deeper than the compiler will follow
+    let server_routes = beep_route
         .or(start_route)
         .or(exec_route)
         .or(stop_route)
+        .or(restart_route)
+        .or(backup_route)
+        .or(limits_route)
+        .or(get_schedule_route)
+        .or(set_schedule_route)
         .or(full_output_route)
         .or(full_route)
+        .or(tps_route)
         .or(partial_route)
+        .or(stats_route)
+        .or(metrics_route)
         .or(new_route)
         .or(list_route)
         .or(rm_route)
-        .or(output_route)
+        .or(clone_route)
+        .or(upgrade_route)
+        .or(upgrade_status_route)
+        .boxed();
+
+    let log_routes = output_route
+        .or(events_route)
+        .or(log_files_route)
+        .or(log_file_route)
+        .or(global_sse_route)
+        .or(sse_route)
+        .or(chat_stream_route)
+        .or(chat_route)
+        .or(modules_route)
+        .or(module_self_route)
+        .or(deliveries_route)
+        .or(crashes_route)
+        .or(auto_restart_route)
+        .boxed();
+
+    let player_routes = roster_route
+        .or(player_list_route)
+        .or(player_add_route)
+        .or(player_remove_route)
+        .or(kick_route)
+        .or(get_properties_route)
+        .or(patch_properties_route)
+        .boxed();
+
+    let content_routes = list_files_route
+        .or(download_route)
+        .or(zip_route)
+        .or(upload_route)
+        .or(delete_file_route)
+        .or(rename_route)
+        .or(mkdir_route)
+        .or(worlds_route)
+        .or(world_download_route)
+        .or(world_upload_route)
+        .or(world_reset_route)
+        .or(world_switch_route)
+        .or(mod_search_route)
+        .or(mods_route)
+        .or(mod_update_all_route)
+        .or(mod_update_route)
+        .or(mod_install_route)
+        .or(mod_remove_route)
+        .boxed();
+
+    let routes = module_scope(modules.clone())
+        .and(server_routes.or(log_routes).or(player_routes).or(content_routes))
         .with(warp::cors().allow_any_origin())
-        .recover(handle_rejection);
+        .recover(handle_rejection)
+        .with(warp::log::custom(|info| {
+            crate::metrics::record_request(info.path(), info.method().as_str(), info.status().as_u16(), info.elapsed());
+        }));
 
     println!("Everything loaded in, starting Web Server on port {} now...", config.ws_port);
 
     warp::serve(routes).run(([127, 0, 0, 1], config.ws_port)).await;
 }
 
+/// Calls made with a module token have to stay inside that module's scope
+fn module_scope(modules: Modules) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::path::full()
+        .and(warp::header::optional::<String>("authorization"))
+        .and(with(modules))
+        .and_then(module_scope_handler)
+        .untuple_one()
+}
+
 fn with<T>(items: T) -> impl Filter<Extract = (T,), Error = Infallible> + Clone
     where T: Clone + Send 
 {
@@ -0,0 +1,333 @@
+use std::fs;
+use std::net::IpAddr;
+use std::str::FromStr;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::error::Error;
+use crate::server::Server;
+
+/// The player lists a server keeps next to its world
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlayerList {
+    Whitelist,
+    Ops,
+    Bans,
+    IpBans,
+}
+
+impl FromStr for PlayerList {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<PlayerList, ()> {
+        match s {
+            "whitelist" => Ok(PlayerList::Whitelist),
+            "ops" => Ok(PlayerList::Ops),
+            "bans" => Ok(PlayerList::Bans),
+            "ipbans" => Ok(PlayerList::IpBans),
+            _ => Err(()),
+        }
+    }
+}
+
+impl PlayerList {
+    fn file(&self) -> &str {
+        match self {
+            PlayerList::Whitelist => "whitelist.json",
+            PlayerList::Ops => "ops.json",
+            PlayerList::Bans => "banned-players.json",
+            PlayerList::IpBans => "banned-ips.json",
+        }
+    }
+
+    // What an entry is known by, IP bans don't have a player
+    fn key(&self) -> &str {
+        match self {
+            PlayerList::IpBans => "ip",
+            _ => "name",
+        }
+    }
+
+    fn add_command(&self, target: &str, reason: Option<&str>) -> Vec<String> {
+        let cmd = match self {
+            PlayerList::Whitelist => vec!["whitelist", "add"],
+            PlayerList::Ops => vec!["op"],
+            PlayerList::Bans => vec!["ban"],
+            PlayerList::IpBans => vec!["ban-ip"],
+        };
+        let mut cmd: Vec<String> = cmd.into_iter().map(|s| s.to_string()).collect();
+        cmd.push(target.to_string());
+        if let (PlayerList::Bans | PlayerList::IpBans, Some(r)) = (self, reason) {
+            cmd.push(r.to_string());
+        }
+        cmd
+    }
+
+    fn remove_command(&self, target: &str) -> Vec<String> {
+        let cmd = match self {
+            PlayerList::Whitelist => vec!["whitelist", "remove"],
+            PlayerList::Ops => vec!["deop"],
+            PlayerList::Bans => vec!["pardon"],
+            PlayerList::IpBans => vec!["pardon-ip"],
+        };
+        let mut cmd: Vec<String> = cmd.into_iter().map(|s| s.to_string()).collect();
+        cmd.push(target.to_string());
+        cmd
+    }
+}
+
+/// Someone to add to one of the lists, `player` is an IP for IP bans
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlayerAction {
+    pub player: String,
+    pub reason: Option<String>,
+}
+
+/// Usernames can only ever be these, anything else could sneak another command in
+fn check_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.len() > 16 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(Error::from("Not a valid player name"));
+    }
+    Ok(())
+}
+
+fn check_target(list: PlayerList, target: &str) -> Result<(), Error> {
+    match list {
+        PlayerList::IpBans => if target.parse::<IpAddr>().is_err() {
+            return Err(Error::from("Not a valid IP address"));
+        },
+        _ => check_name(target)?,
+    }
+    Ok(())
+}
+
+fn clean_reason(reason: &Option<String>) -> Option<String> {
+    reason.as_ref()
+        .map(|r| r.chars().filter(|c| !c.is_control() && *c != '§').collect::<String>())
+        .map(|r| r.trim().to_string())
+        .filter(|r| !r.is_empty())
+}
+
+fn read_file(server: &Server, file: &str) -> Result<Vec<Value>, Error> {
+    let contents = match fs::read_to_string(format!("{}/{file}", server.path)) {
+        Ok(c) => c,
+        // The server only writes these once there's something in them
+        Err(_) => return Ok(Vec::new()),
+    };
+    if let Ok(v) = serde_json::from_str(&contents) { Ok(v) } else {
+        Err(Error::from("Error parsing player list"))
+    }
+}
+
+fn write_file(server: &Server, file: &str, entries: &[Value]) -> Result<(), Error> {
+    let json = if let Ok(j) = serde_json::to_string_pretty(entries) { j } else {
+        return Err(Error::from("Error serializing player list"));
+    };
+    if let Err(_) = fs::write(format!("{}/{file}", server.path), json) {
+        return Err(Error::from("Error writing player list"));
+    }
+    Ok(())
+}
+
+/// Finds the UUID and properly capitalised name of someone who has joined before
+pub fn lookup(server: &Server, name: &str) -> Result<(String, String), Error> {
+    read_file(server, "usercache.json")?
+        .iter()
+        .find(|e| e["name"].as_str().map(|n| n.eq_ignore_ascii_case(name)).unwrap_or(false))
+        .and_then(|e| Some((e["uuid"].as_str()?.to_string(), e["name"].as_str()?.to_string())))
+        .ok_or(Error::from("Player has never joined the server, so their UUID isn't known"))
+}
+
+/// Everything on one of the lists, straight from the file the server keeps it in
+pub fn list(server: &Server, list: PlayerList) -> Result<Vec<Value>, Error> {
+    read_file(server, list.file())
+}
+
+/// Adds someone to a list, through the server itself when it's running so it takes effect right away
+pub async fn add(server: &Server, list: PlayerList, action: &PlayerAction, running: bool) -> Result<String, Error> {
+    check_target(list, &action.player)?;
+    let reason = clean_reason(&action.reason);
+
+    if running {
+        return server.send_command(list.add_command(&action.player, reason.as_deref())).await;
+    }
+
+    let mut entries = read_file(server, list.file())?;
+    let key = list.key();
+    if entries.iter().any(|e| e[key].as_str().map(|v| v.eq_ignore_ascii_case(&action.player)).unwrap_or(false)) {
+        return Ok(format!("{} is already on the list", action.player));
+    }
+
+    let created = Utc::now().format("%Y-%m-%d %H:%M:%S %z").to_string();
+    let reason = reason.unwrap_or("Banned by an operator.".to_string());
+    let entry = match list {
+        PlayerList::IpBans => json!({
+            "ip": action.player,
+            "created": created,
+            "source": "mc-docker",
+            "expires": "forever",
+            "reason": reason,
+        }),
+        _ => {
+            let (uuid, name) = lookup(server, &action.player)?;
+            match list {
+                PlayerList::Whitelist => json!({ "uuid": uuid, "name": name }),
+                PlayerList::Ops => json!({ "uuid": uuid, "name": name, "level": 4, "bypassesPlayerLimit": false }),
+                _ => json!({
+                    "uuid": uuid,
+                    "name": name,
+                    "created": created,
+                    "source": "mc-docker",
+                    "expires": "forever",
+                    "reason": reason,
+                }),
+            }
+        },
+    };
+    entries.push(entry);
+    write_file(server, list.file(), &entries)?;
+    Ok(format!("Added {} to {}", action.player, list.file()))
+}
+
+/// Takes someone off a list
+pub async fn remove(server: &Server, list: PlayerList, target: &str, running: bool) -> Result<String, Error> {
+    check_target(list, target)?;
+
+    if running {
+        return server.send_command(list.remove_command(target)).await;
+    }
+
+    let mut entries = read_file(server, list.file())?;
+    let key = list.key();
+    let before = entries.len();
+    entries.retain(|e| !e[key].as_str().map(|v| v.eq_ignore_ascii_case(target)).unwrap_or(false));
+    if entries.len() == before {
+        return Err(Error::from("Player isn't on the list"));
+    }
+    write_file(server, list.file(), &entries)?;
+    Ok(format!("Removed {target} from {}", list.file()))
+}
+
+/// Kicks someone, which only makes sense while the server is up
+pub async fn kick(server: &Server, action: &PlayerAction) -> Result<String, Error> {
+    check_name(&action.player)?;
+    let mut cmd = vec!["kick".to_string(), action.player.clone()];
+    if let Some(r) = clean_reason(&action.reason) {
+        cmd.push(r);
+    }
+    server.send_command(cmd).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A server directory where Steve and Alex have joined before
+    struct Dir {
+        server: Server,
+    }
+
+    impl Dir {
+        fn new(name: &str) -> Dir {
+            let path = std::env::temp_dir().join(format!("mc-docker-players-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join("usercache.json"), json!([
+                { "name": "Steve", "uuid": "8667ba71-b85a-4004-af54-457a9734eed7", "expiresOn": "2099-01-01 00:00:00 +0000" },
+                { "name": "Alex", "uuid": "ec561538-f3fd-461d-aff5-086b22154bce", "expiresOn": "2099-01-01 00:00:00 +0000" },
+            ]).to_string()).unwrap();
+            let server = serde_json::from_value(json!({
+                "name": name,
+                "id": "",
+                "path": path.to_string_lossy(),
+                "port": 25565,
+            })).unwrap();
+            Dir { server }
+        }
+
+        fn entries(&self, l: PlayerList) -> Vec<Value> {
+            list(&self.server, l).unwrap()
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.server.path);
+        }
+    }
+
+    fn action(player: &str, reason: Option<&str>) -> PlayerAction {
+        PlayerAction { player: player.to_string(), reason: reason.map(|r| r.to_string()) }
+    }
+
+    #[test]
+    fn names_and_ips() {
+        for name in ["Steve", "a_b", "x", "sixteen_chars_ok"] {
+            assert!(check_target(PlayerList::Whitelist, name).is_ok(), "{name}");
+        }
+        for name in ["", "seventeen_chars_x", "Steve; op Alex", "Stéve", "a b", "127.0.0.1"] {
+            assert!(check_target(PlayerList::Ops, name).is_err(), "{name}");
+        }
+        for ip in ["127.0.0.1", "::1", "2001:db8::5"] {
+            assert!(check_target(PlayerList::IpBans, ip).is_ok(), "{ip}");
+        }
+        for ip in ["Steve", "256.0.0.1", "1.2.3", "1.2.3.4 op Steve"] {
+            assert!(check_target(PlayerList::IpBans, ip).is_err(), "{ip}");
+        }
+    }
+
+    #[test]
+    fn looks_up_uuids() {
+        let dir = Dir::new("lookup");
+        let (uuid, name) = lookup(&dir.server, "steve").unwrap();
+        assert_eq!((uuid.as_str(), name.as_str()), ("8667ba71-b85a-4004-af54-457a9734eed7", "Steve"));
+        assert!(lookup(&dir.server, "Herobrine").is_err());
+    }
+
+    #[tokio::test]
+    async fn whitelist_and_ops() {
+        let dir = Dir::new("whitelist");
+        for l in [PlayerList::Whitelist, PlayerList::Ops] {
+            assert!(dir.entries(l).is_empty());
+            add(&dir.server, l, &action("steve", None), false).await.unwrap();
+            // Already on it, in any case
+            add(&dir.server, l, &action("STEVE", None), false).await.unwrap();
+            add(&dir.server, l, &action("Alex", None), false).await.unwrap();
+            // Never joined, so there's no UUID to put down
+            assert!(add(&dir.server, l, &action("Herobrine", None), false).await.is_err());
+
+            let entries = dir.entries(l);
+            assert_eq!(entries.len(), 2);
+            assert_eq!(entries[0]["name"], "Steve");
+            assert_eq!(entries[0]["uuid"], "8667ba71-b85a-4004-af54-457a9734eed7");
+
+            remove(&dir.server, l, "STEVE", false).await.unwrap();
+            assert!(remove(&dir.server, l, "Steve", false).await.is_err());
+            assert_eq!(dir.entries(l).len(), 1);
+            assert_eq!(dir.entries(l)[0]["name"], "Alex");
+        }
+        assert_eq!(dir.entries(PlayerList::Ops)[0]["level"], 4);
+    }
+
+    #[tokio::test]
+    async fn bans() {
+        let dir = Dir::new("bans");
+        add(&dir.server, PlayerList::Bans, &action("Steve", Some("griefing\n§c")), false).await.unwrap();
+        add(&dir.server, PlayerList::Bans, &action("Steve", None), false).await.unwrap();
+        add(&dir.server, PlayerList::IpBans, &action("10.0.0.1", None), false).await.unwrap();
+        add(&dir.server, PlayerList::IpBans, &action("10.0.0.1", None), false).await.unwrap();
+
+        let bans = dir.entries(PlayerList::Bans);
+        assert_eq!(bans.len(), 1);
+        assert_eq!((&bans[0]["name"], &bans[0]["reason"], &bans[0]["expires"]), (&json!("Steve"), &json!("griefingc"), &json!("forever")));
+        let ip_bans = dir.entries(PlayerList::IpBans);
+        assert_eq!(ip_bans.len(), 1);
+        assert_eq!((&ip_bans[0]["ip"], &ip_bans[0]["reason"]), (&json!("10.0.0.1"), &json!("Banned by an operator.")));
+
+        remove(&dir.server, PlayerList::IpBans, "10.0.0.1", false).await.unwrap();
+        remove(&dir.server, PlayerList::Bans, "steve", false).await.unwrap();
+        assert!(dir.entries(PlayerList::Bans).is_empty());
+        assert!(dir.entries(PlayerList::IpBans).is_empty());
+        assert!(remove(&dir.server, PlayerList::Bans, "not a name", false).await.is_err());
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+use std::fs;
+use serde::Serialize;
+use serde_json::Value;
+use crate::error::Error;
+use crate::server::Server;
+
+enum Line {
+    // Comments, blank lines and anything else we don't understand, written back as they were
+    Raw(String),
+    Property { key: String, value: String, raw: String },
+}
+
+/// server.properties, kept line by line so comments and order survive an edit
+pub struct Properties {
+    lines: Vec<Line>,
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                if let Some(c) = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                    out.push(c);
+                }
+            },
+            Some(c) => out.push(c),
+            None => {},
+        }
+    }
+    out
+}
+
+// Same as Java writes them, so the server reads back exactly what was set
+fn escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            ':' => out.push_str("\\:"),
+            '=' => out.push_str("\\="),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if !c.is_ascii() || c.is_ascii_control() => out.push_str(&format!("\\u{:04X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+impl Properties {
+    pub fn parse(file: &str) -> Properties {
+        let lines = file.lines().map(|line| {
+            let trimmed = line.trim_start();
+            if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('!') {
+                return Line::Raw(line.to_string());
+            }
+            // The key ends at the first unescaped = or :
+            let mut escaped = false;
+            let split = trimmed.char_indices().find(|(_, c)| {
+                let found = !escaped && (*c == '=' || *c == ':');
+                escaped = !escaped && *c == '\\';
+                found
+            });
+            match split {
+                Some((i, _)) => Line::Property {
+                    key: unescape(trimmed[..i].trim()),
+                    value: unescape(trimmed[i + 1..].trim_start()),
+                    raw: line.to_string(),
+                },
+                None => Line::Raw(line.to_string()),
+            }
+        }).collect();
+        Properties { lines }
+    }
+
+    pub fn read(server: &Server) -> Result<Properties, Error> {
+        if let Ok(f) = fs::read_to_string(format!("{}/server.properties", server.path)) {
+            Ok(Properties::parse(&f))
+        } else {
+            Err(Error::from("Error reading server.properties, the server may not have started yet"))
+        }
+    }
+
+    pub fn write(&self, server: &Server) -> Result<(), Error> {
+        let mut out = String::new();
+        for line in &self.lines {
+            match line {
+                Line::Raw(raw) | Line::Property { raw, .. } => out.push_str(raw),
+            }
+            out.push('\n');
+        }
+        if let Err(_) = fs::write(format!("{}/server.properties", server.path), out) {
+            return Err(Error::from("Error writing server.properties"));
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines.iter().find_map(|l| match l {
+            Line::Property { key: k, value, .. } if k == key => Some(value.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn map(&self) -> BTreeMap<String, String> {
+        self.lines.iter().filter_map(|l| match l {
+            Line::Property { key, value, .. } => Some((key.clone(), value.clone())),
+            _ => None,
+        }).collect()
+    }
+
+    /// Sets a property in place, or adds it at the end when it's new
+    pub fn set(&mut self, key: &str, value: &str) {
+        let raw = format!("{}={}", escape(key), escape(value));
+        for line in self.lines.iter_mut() {
+            if let Line::Property { key: k, .. } = line {
+                if k == key {
+                    *line = Line::Property { key: key.to_string(), value: value.to_string(), raw };
+                    return;
+                }
+            }
+        }
+        self.lines.push(Line::Property { key: key.to_string(), value: value.to_string(), raw });
+    }
+}
+
+const BOOLS: [&str; 27] = [
+    "accepts-transfers", "allow-flight", "allow-nether", "broadcast-console-to-ops", "broadcast-rcon-to-ops",
+    "enable-command-block", "enable-jmx-monitoring", "enable-query", "enable-rcon", "enable-status",
+    "enforce-secure-profile", "enforce-whitelist", "force-gamemode", "generate-structures", "hardcore",
+    "hide-online-players", "log-ips", "online-mode", "prevent-proxy-connections", "pvp",
+    "require-resource-pack", "spawn-animals", "spawn-monsters", "spawn-npcs", "sync-chunk-writes",
+    "use-native-transport", "white-list",
+];
+
+fn range(key: &str) -> Option<(i64, i64)> {
+    Some(match key {
+        "view-distance" | "simulation-distance" => (3, 32),
+        "server-port" | "rcon.port" | "query.port" => (1, 65535),
+        "op-permission-level" | "function-permission-level" => (0, 4),
+        "max-players" => (0, i32::MAX as i64),
+        "max-world-size" => (1, 29999984),
+        "max-tick-time" | "network-compression-threshold" => (-1, i64::MAX),
+        "spawn-protection" | "player-idle-timeout" | "rate-limit" | "max-chained-neighbor-updates" => (0, i32::MAX as i64),
+        "entity-broadcast-range-percentage" => (10, 1000),
+        _ => return None,
+    })
+}
+
+/// Checks a value against what the server accepts for the keys we know, handing back how it's written
+pub fn validate(key: &str, value: &Value) -> Result<String, Error> {
+    let value = match value {
+        Value::String(s) => s.clone(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        _ => return Err(Error::from("Properties can only be strings, numbers or booleans")),
+    };
+    if key.is_empty() || key.chars().any(|c| c.is_whitespace() || c == '=' || c == ':') {
+        return Err(Error::from("Not a valid property name"));
+    }
+
+    if BOOLS.contains(&key) && value != "true" && value != "false" {
+        return Err(Error::from("Property has to be true or false"));
+    }
+    if let Some((min, max)) = range(key) {
+        match value.parse::<i64>() {
+            Ok(n) if n >= min && n <= max => {},
+            _ => return Err(Error::from("Property is not a number in the allowed range")),
+        }
+    }
+    let allowed: &[&str] = match key {
+        "difficulty" => &["peaceful", "easy", "normal", "hard", "0", "1", "2", "3"],
+        "gamemode" => &["survival", "creative", "adventure", "spectator", "0", "1", "2", "3"],
+        _ => &[],
+    };
+    if !allowed.is_empty() && !allowed.contains(&value.as_str()) {
+        return Err(Error::from("Property isn't one of the allowed values"));
+    }
+    Ok(value)
+}
+
+/// What happened to a patch
+#[derive(Serialize, Debug, Default)]
+pub struct PatchResult {
+    pub changed: Vec<String>,
+    /// Changed while the server was running, so they only apply after a restart
+    pub restart_required: Vec<String>,
+    /// Set by the compose environment, the image writes those back over the file on every start
+    pub overridden: Vec<String>,
+}
+
+/// Validates and writes every property in the patch, applying what can be through commands
+/// when the server is running
+pub async fn patch(server: &Server, patch: &BTreeMap<String, Value>, running: bool) -> Result<PatchResult, Error> {
+    let mut props = Properties::read(server)?;
+
+    // All or nothing, nothing is written if a single value is wrong
+    let mut values = Vec::new();
+    for (key, value) in patch {
+        values.push((key.clone(), validate(key, value)?));
+    }
+
+    let mut result = PatchResult::default();
+    let overrides = server.env_properties();
+    for (key, value) in values {
+        if props.get(&key) == Some(value.as_str()) {
+            continue;
+        }
+        props.set(&key, &value);
+        if overrides.contains(&key.as_str()) {
+            result.overridden.push(key.clone());
+        }
+        result.changed.push(key);
+    }
+    props.write(server)?;
+
+    if running {
+        for key in &result.changed {
+            let value = props.get(key).unwrap_or_default();
+            // The few that have a command to change them without a restart
+            let cmd = match key.as_str() {
+                "difficulty" => Some(vec!["difficulty".to_string(), value.to_string()]),
+                "white-list" => Some(vec!["whitelist".to_string(), if value == "true" { "on" } else { "off" }.to_string()]),
+                _ => None,
+            };
+            let applied = match cmd {
+                Some(cmd) => server.send_command(cmd).await.is_ok(),
+                None => false,
+            };
+            if !applied {
+                result.restart_required.push(key.clone());
+            }
+        }
+    }
+    Ok(result)
+}
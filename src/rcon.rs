@@ -0,0 +1,247 @@
+use futures::FutureExt;
+use tokio::net::TcpStream;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::{timeout, Duration};
+use crate::error::Error;
+
+// Packet types from the Source RCON protocol, minecraft only uses these
+const RESPONSE_VALUE: i32 = 0;
+const EXEC_COMMAND: i32 = 2;
+const AUTH: i32 = 3;
+
+// Minecraft won't accept a command longer than this
+const MAX_COMMAND: usize = 1446;
+const MAX_PACKET: i32 = 4096 + 10;
+
+/// A logged in connection to a server's RCON port
+#[derive(Debug)]
+pub struct Rcon {
+    stream: TcpStream,
+    next_id: i32,
+}
+
+/// Why a command failed, which decides whether it's safe to send it again
+#[derive(Debug)]
+pub enum Failure {
+    /// Nothing reached the server
+    Unsent(Error),
+    /// The command went out, so the server may well have run it already
+    Unanswered(Error),
+}
+
+impl Failure {
+    pub fn error(self) -> Error {
+        match self {
+            Failure::Unsent(e) | Failure::Unanswered(e) => e,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Packet {
+    id: i32,
+    kind: i32,
+    body: String,
+}
+
+impl Rcon {
+    pub async fn connect(host: &str, port: u16, password: &str) -> Result<Rcon, Error> {
+        let stream = match timeout(Duration::from_secs(5), TcpStream::connect((host, port))).await {
+            Ok(Ok(s)) => s,
+            _ => return Err(Error::from("Failed to connect to the RCON port")),
+        };
+
+        let mut rcon = Rcon { stream, next_id: 1 };
+        let id = rcon.id();
+        rcon.write(id, AUTH, password).await?;
+
+        // the server answers a login with an auth response carrying our id, or -1 on a bad password
+        let res = rcon.read().await?;
+        if res.id == -1 {
+            return Err(Error::from("RCON password was rejected"));
+        }
+        Ok(rcon)
+    }
+
+    /// Runs a command, stitching together responses that were split over several packets
+    pub async fn cmd(&mut self, cmd: &str) -> Result<String, Failure> {
+        if cmd.len() > MAX_COMMAND {
+            return Err(Failure::Unsent(Error::from("Command is too long to send over RCON")));
+        }
+        if !self.is_open() {
+            return Err(Failure::Unsent(Error::from("RCON connection was closed")));
+        }
+
+        let id = self.id();
+        let end = self.id();
+        self.write(id, EXEC_COMMAND, cmd).await.map_err(Failure::Unsent)?;
+        // Minecraft replies to an unknown packet type after everything before it has been
+        // answered, so this marks where the response to the command ends
+        self.write(end, RESPONSE_VALUE, "").await.map_err(Failure::Unanswered)?;
+
+        let mut body = String::new();
+        loop {
+            let res = self.read().await.map_err(Failure::Unanswered)?;
+            if res.id == end {
+                return Ok(body);
+            } else if res.id == id && res.kind == RESPONSE_VALUE {
+                body.push_str(&res.body);
+            }
+        }
+    }
+
+    /// Nothing should be waiting to be read between commands, so anything there, the end of the
+    /// stream included, means the connection can't be used any more
+    fn is_open(&self) -> bool {
+        let mut buf = [0u8; 1];
+        self.stream.peek(&mut buf).now_or_never().is_none()
+    }
+
+    fn id(&mut self) -> i32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        id
+    }
+
+    async fn write(&mut self, id: i32, kind: i32, body: &str) -> Result<(), Error> {
+        let mut buf = Vec::with_capacity(body.len() + 14);
+        buf.extend_from_slice(&(body.len() as i32 + 10).to_le_bytes());
+        buf.extend_from_slice(&id.to_le_bytes());
+        buf.extend_from_slice(&kind.to_le_bytes());
+        buf.extend_from_slice(body.as_bytes());
+        buf.extend_from_slice(&[0, 0]);
+
+        if let Err(_) = self.stream.write_all(&buf).await {
+            return Err(Error::from("Failed to write to the RCON connection"));
+        }
+        Ok(())
+    }
+
+    async fn read(&mut self) -> Result<Packet, Error> {
+        let len = match timeout(Duration::from_secs(10), self.stream.read_i32_le()).await {
+            Ok(Ok(l)) => l,
+            _ => return Err(Error::from("Failed to read from the RCON connection")),
+        };
+        if !(10..=MAX_PACKET).contains(&len) {
+            return Err(Error::from("Got a malformed RCON packet"));
+        }
+
+        let mut buf = vec![0; len as usize];
+        if let Err(_) = self.stream.read_exact(&mut buf).await {
+            return Err(Error::from("Failed to read from the RCON connection"));
+        }
+
+        let id = i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        let kind = i32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        // the body is followed by two null bytes
+        let body = String::from_utf8_lossy(&buf[8..buf.len() - 2]).to_string();
+
+        Ok(Packet { id, kind, body })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use super::*;
+
+    /// Both ends of a connection, each speaking the protocol through `Rcon`
+    async fn pair() -> (Rcon, Rcon) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (Rcon { stream: client, next_id: 1 }, Rcon { stream: server, next_id: 1 })
+    }
+
+    /// Answers a login then a single command with `parts`, one packet each
+    async fn fake_server(parts: Vec<&'static str>) -> (u16, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut rcon = Rcon { stream, next_id: 1 };
+
+            let auth = rcon.read().await.unwrap();
+            assert_eq!(auth.kind, AUTH);
+            let id = if auth.body == "secret" { auth.id } else { -1 };
+            rcon.write(id, EXEC_COMMAND, "").await.unwrap();
+
+            let cmd = rcon.read().await.unwrap();
+            assert_eq!(cmd.kind, EXEC_COMMAND);
+            let end = rcon.read().await.unwrap();
+            for part in parts {
+                rcon.write(cmd.id, RESPONSE_VALUE, part).await.unwrap();
+            }
+            rcon.write(end.id, RESPONSE_VALUE, &format!("Unknown request {:x}", end.kind)).await.unwrap();
+            cmd.body
+        });
+        (port, handle)
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let (mut client, mut server) = pair().await;
+        for (id, kind, body) in [(1, AUTH, "secret"), (7, EXEC_COMMAND, "say héllo"), (-1, RESPONSE_VALUE, "")] {
+            client.write(id, kind, body).await.unwrap();
+            let packet = server.read().await.unwrap();
+            assert_eq!((packet.id, packet.kind, packet.body.as_str()), (id, kind, body));
+        }
+    }
+
+    #[tokio::test]
+    async fn packet_layout() {
+        let (mut client, mut server) = pair().await;
+        client.write(3, EXEC_COMMAND, "list").await.unwrap();
+        let mut buf = [0u8; 18];
+        server.stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, [14, 0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, b'l', b'i', b's', b't', 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn malformed_length() {
+        let (mut client, mut server) = pair().await;
+        server.stream.write_all(&5i32.to_le_bytes()).await.unwrap();
+        assert!(client.read().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn split_response() {
+        let (port, server) = fake_server(vec!["There are 2 of a max of 20 players online: ", "Alex, ", "Steve"]).await;
+        let mut rcon = Rcon::connect("127.0.0.1", port, "secret").await.unwrap();
+        assert_eq!(rcon.cmd("list").await.unwrap(), "There are 2 of a max of 20 players online: Alex, Steve");
+        assert_eq!(server.await.unwrap(), "list");
+    }
+
+    #[tokio::test]
+    async fn empty_response() {
+        let (port, _server) = fake_server(vec![]).await;
+        let mut rcon = Rcon::connect("127.0.0.1", port, "secret").await.unwrap();
+        assert_eq!(rcon.cmd("save-all").await.unwrap(), "");
+    }
+
+    #[tokio::test]
+    async fn wrong_password() {
+        let (port, _server) = fake_server(vec![]).await;
+        assert!(Rcon::connect("127.0.0.1", port, "wrong").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn closed_before_sending() {
+        let (mut client, server) = pair().await;
+        drop(server);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(matches!(client.cmd("give Steve diamond").await, Err(Failure::Unsent(_))));
+    }
+
+    #[tokio::test]
+    async fn closed_after_sending() {
+        let (mut client, mut server) = pair().await;
+        let handle = tokio::spawn(async move {
+            let cmd = server.read().await.unwrap();
+            drop(server);
+            cmd.body
+        });
+        assert!(matches!(client.cmd("give Steve diamond").await, Err(Failure::Unanswered(_))));
+        assert_eq!(handle.await.unwrap(), "give Steve diamond");
+    }
+}
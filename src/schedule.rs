@@ -0,0 +1,233 @@
+use chrono::{DateTime, Datelike, Local, Timelike};
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, Duration};
+use crate::{Servers, Config, States};
+use crate::server::Server;
+use crate::error::Error;
+
+/// Something that should happen to a server whenever its cron expression matches
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Schedule {
+    pub cron: String,
+    pub action: Action,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Action {
+    Restart,
+    Backup,
+    Command { args: Vec<String> },
+}
+
+impl Schedule {
+    /// Makes sure the cron expression can be parsed before it gets stored
+    pub fn validate(&self) -> Result<(), Error> {
+        Cron::parse(&self.cron).map(|_| ())
+    }
+}
+
+/// A standard 5 field cron expression: minute, hour, day of month, month and day of week
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // cron treats the day fields as an OR when both of them are restricted
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Result<Cron, Error> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(Error::from("Cron expressions need exactly 5 fields"));
+        }
+
+        let mut weekdays = field(fields[4], 0, 7)?;
+        // both 0 and 7 are sunday
+        if weekdays & (1u64 << 7) != 0 {
+            weekdays |= 1;
+        }
+
+        Ok(Cron {
+            minutes: field(fields[0], 0, 59)?,
+            hours: field(fields[1], 0, 23)?,
+            days: field(fields[2], 1, 31)?,
+            months: field(fields[3], 1, 12)?,
+            weekdays,
+            // like cron, `*/2` still counts as unrestricted, only a field without a star narrows it down
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        })
+    }
+
+    pub fn matches(&self, time: &DateTime<Local>) -> bool {
+        let day = self.days & (1u64 << time.day()) != 0;
+        let weekday = self.weekdays & (1u64 << time.weekday().num_days_from_sunday()) != 0;
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+
+        self.minutes & (1u64 << time.minute()) != 0
+            && self.hours & (1u64 << time.hour()) != 0
+            && self.months & (1u64 << time.month()) != 0
+            && day_matches
+    }
+}
+
+/// Parses one cron field into a bitmask of the values it allows
+fn field(expr: &str, min: u32, max: u32) -> Result<u64, Error> {
+    let mut mask = 0;
+    for part in expr.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((r, s)) => (r, s.parse::<u32>().map_err(|_| Error::from("Invalid step in cron expression"))?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(Error::from("Invalid step in cron expression"));
+        }
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (value(a)?, value(b)?)
+        } else {
+            let v = value(range)?;
+            // `5/10` means every 10 starting at 5
+            if part.contains('/') { (v, max) } else { (v, v) }
+        };
+
+        if start < min || end > max || start > end {
+            return Err(Error::from("Value out of range in cron expression"));
+        }
+
+        for v in (start..=end).step_by(step as usize) {
+            mask |= 1u64 << v;
+        }
+    }
+    Ok(mask)
+}
+
+fn value(s: &str) -> Result<u32, Error> {
+    s.parse::<u32>().map_err(|_| Error::from("Invalid value in cron expression"))
+}
+
+/// Checks every server's schedules at the start of each minute and runs the ones that match
+pub async fn run_scheduler(servers: Servers, config: Config, states: States) {
+    loop {
+        let now = Local::now();
+        sleep(Duration::from_secs(60 - now.second() as u64)).await;
+        let now = Local::now();
+
+        let due: Vec<(Server, Action)> = servers.read().await.values()
+            .flat_map(|s| s.schedules.iter()
+                .filter(|sch| Cron::parse(&sch.cron).map(|c| c.matches(&now)).unwrap_or(false))
+                .map(|sch| (s.clone(), sch.action.clone()))
+                .collect::<Vec<_>>())
+            .collect();
+
+        for (server, action) in due {
+            let config = config.clone();
+            let states = states.clone();
+            tokio::spawn(async move {
+                let name = format!("{:?}", action);
+                println!("Running scheduled {} on {}", name, server.name);
+                let result = match action {
+                    Action::Restart => {
+                        states.expect_stop(&server.name, &config.stop);
+                        let restarted = server.restart(&config.stop).await;
+                        states.stop_done(&server.name);
+                        restarted
+                    },
+                    Action::Backup => server.backup(&config).await.map(|_| ()),
+                    Action::Command { args } => server.send_command(args).await.map(|_| ()),
+                };
+                if let Err(e) = result {
+                    println!("Scheduled {} on {} failed: {:?}", name, server.name, e);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use super::*;
+
+    fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap()
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(field("*", 0, 5).unwrap(), 0b111111);
+        assert_eq!(field("3", 0, 59).unwrap(), 1 << 3);
+        assert_eq!(field("1-3", 0, 59).unwrap(), 0b1110);
+        assert_eq!(field("1,4,6", 0, 59).unwrap(), (1 << 1) | (1 << 4) | (1 << 6));
+        assert_eq!(field("*/15", 0, 59).unwrap(), (1 << 0) | (1 << 15) | (1 << 30) | (1 << 45));
+        assert_eq!(field("10-20/5", 0, 59).unwrap(), (1 << 10) | (1 << 15) | (1 << 20));
+        assert_eq!(field("50/5", 0, 59).unwrap(), (1 << 50) | (1 << 55));
+        assert_eq!(field("1-2,*/30", 0, 59).unwrap(), (1 << 0) | (1 << 1) | (1 << 2) | (1 << 30));
+    }
+
+    #[test]
+    fn invalid() {
+        for expr in ["* * * *", "* * * * * *", "60 * * * *", "* 24 * * *", "* * 0 * *", "* * * 13 *",
+            "* * * * 8", "*/0 * * * *", "5-1 * * * *", "a * * * *", "1-b * * * *", "*/x * * * *"] {
+            assert!(Cron::parse(expr).is_err(), "{expr} should be invalid");
+        }
+    }
+
+    #[test]
+    fn sunday_is_0_and_7() {
+        // 2024-01-07 was a sunday
+        assert!(Cron::parse("0 0 * * 0").unwrap().matches(&at(2024, 1, 7, 0, 0)));
+        assert!(Cron::parse("0 0 * * 7").unwrap().matches(&at(2024, 1, 7, 0, 0)));
+        assert!(!Cron::parse("0 0 * * 7").unwrap().matches(&at(2024, 1, 8, 0, 0)));
+    }
+
+    #[test]
+    fn times() {
+        let cron = Cron::parse("*/15 9-17 * * 1-5").unwrap();
+        assert!(cron.matches(&at(2024, 1, 8, 9, 0)));
+        assert!(cron.matches(&at(2024, 1, 8, 17, 45)));
+        assert!(!cron.matches(&at(2024, 1, 8, 17, 46)));
+        assert!(!cron.matches(&at(2024, 1, 8, 18, 0)));
+        // saturday
+        assert!(!cron.matches(&at(2024, 1, 6, 9, 0)));
+    }
+
+    #[test]
+    fn day_fields_or_when_both_restricted() {
+        // the 1st of the month or any monday
+        let cron = Cron::parse("0 0 1 * 1").unwrap();
+        assert!(cron.matches(&at(2024, 2, 1, 0, 0)));
+        assert!(cron.matches(&at(2024, 1, 8, 0, 0)));
+        assert!(!cron.matches(&at(2024, 1, 9, 0, 0)));
+    }
+
+    #[test]
+    fn day_fields_and_when_one_is_starred() {
+        let cron = Cron::parse("0 0 * * 1").unwrap();
+        assert!(cron.matches(&at(2024, 1, 8, 0, 0)));
+        assert!(!cron.matches(&at(2024, 2, 1, 0, 0)));
+
+        // mondays on odd days of the month, not every monday and every odd day
+        let cron = Cron::parse("0 0 */2 * 1").unwrap();
+        assert!(cron.matches(&at(2024, 1, 1, 0, 0)));
+        assert!(!cron.matches(&at(2024, 1, 8, 0, 0)));
+        assert!(!cron.matches(&at(2024, 1, 3, 0, 0)));
+
+        let cron = Cron::parse("0 0 13 * */2").unwrap();
+        // 2024-09-13 was a friday, 2024-10-13 a sunday
+        assert!(!cron.matches(&at(2024, 9, 13, 0, 0)));
+        assert!(cron.matches(&at(2024, 10, 13, 0, 0)));
+        assert!(!cron.matches(&at(2024, 10, 14, 0, 0)));
+    }
+}
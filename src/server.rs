@@ -1,16 +1,17 @@
 use bollard::{
     Docker,
-    exec::CreateExecOptions,
-    container::{LogsOptions, LogOutput}
+    exec::{CreateExecOptions, StartExecResults},
+    models::ExecInspectResponse,
+    container::{InspectContainerOptions, LogsOptions, LogOutput, StopContainerOptions, KillContainerOptions, WaitContainerOptions, UpdateContainerOptions}
 };
 use futures::{Stream, stream::StreamExt, future};
 use serde::{Deserialize, Serialize};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
 use std::process::Command;
 use std::path::Path;
 use std::fs;
 use std::io::prelude::*;
-use regex::Regex;
 use hyper::body::Bytes;
 use cloudsync::{CloudSync, Unique, CLConfig};
 use crate::{Config, CONF_PATH};
@@ -18,14 +19,30 @@ use craftping::{
     tokio::ping,
     Response};
 use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, Duration};
+use tokio::sync::Mutex;
+use std::sync::Arc;
 use crate::error::Error;
-
-#[derive(Serialize, Deserialize, Debug)]
+use crate::schedule::Schedule;
+use crate::crash::AutoRestart;
+use crate::limits::{Limits, CPU_PERIOD};
+use crate::rcon::{Failure, Rcon};
+use crate::events::{self, LogEvent};
+use crate::logs;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Server {
     pub name: String,
     pub id: String,
     pub path: String,
     pub port: u16,
+    #[serde(default)]
+    pub schedules: Vec<Schedule>,
+    #[serde(default)]
+    pub auto_restart: AutoRestart,
+    // Shared between clones so every handler reuses the same logged in connection
+    #[serde(skip)]
+    rcon: Arc<Mutex<Option<Rcon>>>,
 }
 
 impl Server {
@@ -38,6 +55,7 @@ impl Server {
         ports: Option<Vec<u16>>, 
         version: Option<String>, 
         server_type: Option<String>, 
+        limits: Option<Limits>,
         
         //server_config: &ServerConfig,
         config: Config
@@ -79,11 +97,7 @@ impl Server {
             };
         }
 
-        let ports = if let Some(p) = ports {
-            p
-        } else {
-            Vec::new()
-        };
+        let ports = ports.unwrap_or_default();
 
         println!("Reading compose file to string");
 
@@ -103,7 +117,7 @@ impl Server {
             return Err(Error::from("Error parsing YAML from default compose file"))
         };
 
-        let port_from_file = if let Some(a) = compose.services.mc.ports.get(0) {
+        let port_from_file = if let Some(a) = compose.services.mc.ports.first() {
             if let Some(b) = a.split(":").next() {
                 if let Ok(c) = b.parse::<u16>() { c } else {
                     return Err(Error::from("Failed parsing port"))
@@ -115,7 +129,7 @@ impl Server {
             return Err(Error::from("Could not get \"ports\" field from YAML"))       
         };
 
-        let def_port = if let Some(a) = def.services.mc.ports.get(0) {
+        let def_port = if let Some(a) = def.services.mc.ports.first() {
             if let Some(b) = a.split(":").next() {
                 if let Ok(c) = b.parse::<u16>() { c } else {
                     return Err(Error::from("Failed parsing port"))
@@ -159,6 +173,11 @@ impl Server {
         if let Some(t) = server_type {
             compose.services.mc.environment.TYPE = Some(t);
         }
+
+        // In the file before the first start, so it never runs without them
+        if let Some(l) = &limits {
+            apply_limits(&mut compose.services.mc, l)?;
+        }
         
         println!("Writing updated compose to compose file");
         println!("Compose path: {compose_str}");
@@ -201,8 +220,8 @@ impl Server {
         };
         println!("Output from docker compose: \n{str_out}");
 
-        let id = if let Some(a) = str_out.split("\n").skip_while(|e| !e.starts_with("Container")).next() {
-            if let Some(b) = a.split(" ").skip(1).next() {
+        let id = if let Some(a) = str_out.split("\n").find(|e| e.starts_with("Container")) {
+            if let Some(b) = a.split(" ").nth(1) {
                 b.to_string()
             } else {
                 return Err(Error::from("Couldn't find container ID"));
@@ -218,6 +237,9 @@ impl Server {
             path,
             id,
             port,
+            schedules: Vec::new(),
+            auto_restart: AutoRestart::default(),
+            rcon: Arc::new(Mutex::new(None)),
         };
 
         if let Err(_) = server.save().await {
@@ -226,7 +248,258 @@ impl Server {
         Ok(server)
     }
 
-    pub async fn send_command(&self, cmd: Vec<String>) -> Result<(), Error> {
+    /// Runs a command over RCON, returning whatever the server replied
+    pub async fn send_command(&self, cmd: Vec<String>) -> Result<String, Error> {
+        let line = cmd.join(" ");
+        let mut conn = self.rcon.lock().await;
+
+        if let Some(c) = conn.as_mut() {
+            match c.cmd(&line).await {
+                Ok(r) => return Ok(r),
+                // Most likely the server restarted since the connection was made
+                Err(Failure::Unsent(_)) => *conn = None,
+                // Sending it again could run something like `give` twice
+                Err(Failure::Unanswered(e)) => {
+                    *conn = None;
+                    return Err(e);
+                },
+            }
+        }
+
+        match self.rcon_login().await {
+            Ok(mut c) => {
+                let res = c.cmd(&line).await;
+                if res.is_ok() {
+                    *conn = Some(c);
+                }
+                res.map_err(Failure::error)
+            },
+            Err(e) => {
+                println!("No RCON connection to {} ({:?}), falling back to rcon-cli", self.name, e);
+                drop(conn);
+                self.exec_command(cmd).await
+            },
+        }
+    }
+
+    /// Logs in to the RCON port of the container with the password from the compose file
+    async fn rcon_login(&self) -> Result<Rcon, Error> {
+        #[cfg(unix)]
+        let docker = if let Ok(d) = Docker::connect_with_socket_defaults() { d } else {
+            return Err(Error::from("Couldn't connect to docker on default socket"));
+        };
+
+        let network = if let Ok(i) = docker.inspect_container(&self.id, None::<InspectContainerOptions>).await {
+            i.network_settings
+        } else {
+            return Err(Error::from("Failed to inspect the container"));
+        };
+
+        let ip = network.and_then(|n| {
+            n.networks
+                .and_then(|nets| nets.into_values().filter_map(|e| e.ip_address).find(|ip| !ip.is_empty()))
+                .or(n.ip_address)
+        });
+        let ip = if let Some(i) = ip.filter(|ip| !ip.is_empty()) { i } else {
+            return Err(Error::from("Container doesn't have an IP address, is it running?"));
+        };
+
+        let env = self.compose()?.services.mc.environment;
+        let port = env.RCON_PORT.unwrap_or(25575);
+        // The image generates a password when none is given and leaves it in the data directory
+        let password = if let Some(p) = env.RCON_PASSWORD { p } else {
+            fs::read_to_string(format!("{}/.rcon-cli.env", self.path))
+                .ok()
+                .and_then(|f| f.lines().find_map(|l| l.strip_prefix("password=").map(|p| p.to_string())))
+                .unwrap_or("minecraft".to_string())
+        };
+
+        Rcon::connect(&ip, port, &password).await
+    }
+
+    /// Reads the compose file in the server directory
+    fn compose(&self) -> Result<Compose, Error> {
+        let file = if let Ok(f) = fs::read_to_string(format!("{}/docker-compose.yml", self.path)) { f } else {
+            return Err(Error::from("Error reading compose file to a string"));
+        };
+        if let Ok(c) = serde_yaml::from_str(&file) { Ok(c) } else {
+            Err(Error::from("Error parsing YAML from compose file"))
+        }
+    }
+
+    /// Every port mapping in the compose file, the game port first
+    pub fn ports(&self) -> Result<Vec<String>, Error> {
+        self.compose().map(|c| c.services.mc.ports)
+    }
+
+    /// The TYPE the image was told to run, VANILLA when it's not set
+    pub fn server_type(&self) -> String {
+        self.compose().ok()
+            .and_then(|c| c.services.mc.environment.TYPE)
+            .unwrap_or("VANILLA".to_string())
+            .to_uppercase()
+    }
+
+    /// The VERSION the image was told to run, which may be LATEST or SNAPSHOT
+    pub fn version(&self) -> Result<String, Error> {
+        Ok(self.compose()?.services.mc.environment.VERSION)
+    }
+
+    /// The VERSION and, for types that have one, the pinned loader or build
+    pub fn versions(&self) -> Result<(String, Option<String>), Error> {
+        let env = self.compose()?.services.mc.environment;
+        let loader = match self.server_type().as_str() {
+            "FORGE" => env.FORGE_VERSION,
+            "NEOFORGE" => env.NEOFORGE_VERSION,
+            "FABRIC" => env.FABRIC_LOADER_VERSION,
+            "QUILT" => env.QUILT_LOADER_VERSION,
+            "PAPER" => env.PAPER_BUILD,
+            "PURPUR" => env.PURPUR_BUILD,
+            _ => None,
+        };
+        Ok((env.VERSION, loader))
+    }
+
+    /// Sets VERSION along with the loader or build of the type, left to the image to pick when None
+    pub fn set_versions(&self, version: &str, loader: Option<String>) -> Result<(), Error> {
+        let server_type = self.server_type();
+        let mut compose = self.compose()?;
+        let env = &mut compose.services.mc.environment;
+        env.VERSION = version.to_string();
+        match server_type.as_str() {
+            "FORGE" => env.FORGE_VERSION = loader,
+            "NEOFORGE" => env.NEOFORGE_VERSION = loader,
+            "FABRIC" => env.FABRIC_LOADER_VERSION = loader,
+            "QUILT" => env.QUILT_LOADER_VERSION = loader,
+            "PAPER" => env.PAPER_BUILD = loader,
+            "PURPUR" => env.PURPUR_BUILD = loader,
+            _ => if loader.is_some() {
+                return Err(Error::from("This server type doesn't have a loader version"));
+            },
+        }
+        self.write_compose(&compose)
+    }
+
+    /// The folder of the world the server loads, `world` unless LEVEL says otherwise
+    pub fn level(&self) -> String {
+        self.compose().ok()
+            .and_then(|c| c.services.mc.environment.LEVEL)
+            .unwrap_or("world".to_string())
+    }
+
+    /// Points LEVEL at another world, only used once the container is recreated
+    pub fn set_level(&self, level: &str) -> Result<(), Error> {
+        let mut compose = self.compose()?;
+        compose.services.mc.environment.LEVEL = Some(level.to_string());
+        self.write_compose(&compose)
+    }
+
+    /// Sets the SEED the next world is generated with, a random one when left out
+    pub fn set_seed(&self, seed: Option<String>) -> Result<(), Error> {
+        let mut compose = self.compose()?;
+        compose.services.mc.environment.SEED = seed;
+        self.write_compose(&compose)
+    }
+
+    fn write_compose(&self, compose: &Compose) -> Result<(), Error> {
+        let yaml = if let Ok(y) = serde_yaml::to_string(compose) { y } else {
+            return Err(Error::from("Error serializing compose file"));
+        };
+        if let Err(_) = fs::write(format!("{}/docker-compose.yml", self.path), yaml) {
+            return Err(Error::from("Failed to write YAML object to file"));
+        }
+        Ok(())
+    }
+
+    /// Recreates the container from the compose file if it changed, leaving it stopped unless
+    /// `start` is set
+    pub async fn compose_up(&self, start: bool) -> Result<(), Error> {
+        let mut cmd = tokio::process::Command::new("docker");
+        cmd.arg("compose").arg("up").arg("-d").current_dir(&self.path);
+        if !start {
+            cmd.arg("--no-start");
+        }
+        let output = if let Ok(o) = cmd.output().await { o } else {
+            return Err(Error::from("Failed to run docker-compose command"));
+        };
+        if !output.status.success() {
+            println!("Output from docker compose: \n{}", String::from_utf8_lossy(&output.stderr));
+            return Err(Error::from("docker compose up failed"));
+        }
+        Ok(())
+    }
+
+    /// The server.properties keys the compose environment sets, which the image rewrites on start
+    pub fn env_properties(&self) -> Vec<&'static str> {
+        let env = if let Ok(c) = self.compose() { c.services.mc.environment } else { return Vec::new() };
+        [
+            (env.DIFFICULTY.is_some(), "difficulty"),
+            (env.MOTD.is_some(), "motd"),
+            (env.MAX_PLAYERS.is_some(), "max-players"),
+            (env.SEED.is_some(), "level-seed"),
+            (env.MODE.is_some(), "gamemode"),
+            (env.ENABLE_WHITELIST.is_some(), "white-list"),
+        ].into_iter().filter(|(set, _)| *set).map(|(_, key)| key).collect()
+    }
+
+    /// The limits written in the compose file
+    pub fn limits(&self) -> Result<Limits, Error> {
+        let mc = self.compose()?.services.mc;
+        Ok(Limits {
+            memory: mc.mem_limit,
+            heap: mc.environment.MEMORY,
+            cpus: mc.cpus,
+            cpu_shares: mc.cpu_shares,
+            pids: mc.pids_limit,
+        })
+    }
+
+    /// Writes new limits to the compose file and applies what docker can change on a running
+    /// container, returns whether the container still has to be recreated for all of it to apply
+    pub async fn set_limits(&self, limits: &Limits, running: bool) -> Result<bool, Error> {
+        let old = self.limits()?;
+        let heap = limits.heap()?;
+
+        let mut compose = self.compose()?;
+        apply_limits(&mut compose.services.mc, limits)?;
+        self.write_compose(&compose)?;
+
+        if !running {
+            // Nothing to lose by recreating it now
+            self.compose_up(false).await?;
+            return Ok(false);
+        }
+
+        #[cfg(unix)]
+        let docker = if let Ok(d) = Docker::connect_with_socket_defaults() { d } else {
+            return Err(Error::from("Couldn't connect to docker on default socket"));
+        };
+        let memory = limits.memory_bytes()?;
+        let options = UpdateContainerOptions::<String> {
+            memory,
+            memory_swap: memory,
+            cpu_shares: limits.cpu_shares.map(|s| s as _),
+            cpu_period: limits.cpus.map(|_| CPU_PERIOD),
+            cpu_quota: limits.cpu_quota(),
+            pids_limit: limits.pids,
+            ..Default::default()
+        };
+        if let Err(e) = docker.update_container(&self.id, options).await {
+            println!("Failed to update limits of {}: {:?}", self.name, e);
+            return Err(Error::from("Failed to update the container's limits"));
+        }
+
+        // Docker can't take limits away from a running container, and the JVM only reads MEMORY on start
+        let removed = (old.memory.is_some() && limits.memory.is_none())
+            || (old.cpus.is_some() && limits.cpus.is_none())
+            || (old.cpu_shares.is_some() && limits.cpu_shares.is_none())
+            || (old.pids.is_some() && limits.pids.is_none());
+        let heap_changed = heap.is_some() && heap != old.heap;
+        Ok(removed || heap_changed)
+    }
+
+    /// Runs a command through rcon-cli in the container, for images we can't reach over RCON
+    async fn exec_command(&self, cmd: Vec<String>) -> Result<String, Error> {
         #[cfg(unix)]
         let docker = if let Ok(d) = Docker::connect_with_socket_defaults() { d } else {
             return Err(Error::from("Couldn't connect to docker on default socket"));
@@ -247,10 +520,27 @@ impl Server {
             return Err(Error::from("Failed creating exec for docker"))
         };
 
-        if let Err(_) = docker.start_exec(&exec, None).await {
-            return Err(Error::from("Failed to send cmd to container"));
+        let mut response = String::new();
+        match docker.start_exec(&exec, None).await {
+            Ok(StartExecResults::Attached { mut output, .. }) => {
+                while let Some(msg) = output.next().await {
+                    match msg {
+                        Ok(m) => response.push_str(&m.to_string()),
+                        Err(_) => return Err(Error::from("Failed reading the output of the cmd")),
+                    }
+                }
+            },
+            Ok(StartExecResults::Detached) => {},
+            Err(_) => return Err(Error::from("Failed to send cmd to container")),
+        }
+
+        match docker.inspect_exec(&exec).await {
+            Ok(ExecInspectResponse { exit_code: Some(0), .. }) => Ok(response),
+            Ok(ExecInspectResponse { exit_code: Some(code), .. }) => {
+                Err(Error::from(&format!("Cmd exited with code {code}: {}", response.trim())))
+            },
+            _ => Err(Error::from("Failed to get the result of the cmd")),
         }
-        Ok(())
     }
 
     pub async fn start(&self) -> Result<(), Error> {
@@ -277,6 +567,93 @@ impl Server {
         Ok(())
     }
 
+    /// Warns the players, saves the world and stops the server from the inside, then stops the
+    /// container through docker so it stays down
+    pub async fn graceful_stop(&self, stop: &StopConfig) -> Result<(), Error> {
+        #[cfg(unix)]
+        let docker = if let Ok(d) = Docker::connect_with_socket_defaults() { d } else {
+            return Err(Error::from("Couldn't connect to docker on default socket"));
+        };
+
+        let mut marks = stop.countdown.clone();
+        marks.sort_unstable_by(|a, b| b.cmp(a));
+        marks.dedup();
+
+        for (i, secs) in marks.iter().enumerate() {
+            let text = stop.message.replace("{seconds}", &secs.to_string());
+            let msg = serde_json::json!({ "text": text, "color": "yellow" }).to_string();
+            if let Err(e) = self.send_command(vec!["tellraw".to_string(), "@a".to_string(), msg.clone()]).await {
+                println!("Failed to warn players on {}: {:?}", self.name, e);
+            }
+            if let Err(e) = self.send_command(vec!["title".to_string(), "@a".to_string(), "actionbar".to_string(), msg]).await {
+                println!("Failed to warn players on {}: {:?}", self.name, e);
+            }
+            let next = marks.get(i + 1).copied().unwrap_or(0);
+            sleep(Duration::from_secs(secs - next)).await;
+        }
+
+        let saved = self.send_command(vec!["save-all".to_string(), "flush".to_string()]).await;
+        let stopped = self.send_command(vec!["stop".to_string()]).await;
+
+        if saved.is_ok() && stopped.is_ok() {
+            let mut wait = docker.wait_container(&self.id, Some(WaitContainerOptions { condition: "not-running" }));
+            // the wait stream resolves once the container has exited, whatever the exit code was
+            if timeout(Duration::from_secs(stop.timeout), wait.next()).await.is_err() {
+                println!("{} didn't exit within {}s, stopping the container through docker", self.name, stop.timeout);
+            }
+        } else {
+            println!("Couldn't stop {} from the inside, stopping the container through docker", self.name);
+        }
+
+        // Even after a clean exit docker still has to stop the container, otherwise the restart
+        // policy brings it straight back up
+        if let Err(_) = docker.stop_container(&self.id, Some(StopContainerOptions { t: stop.grace as i64 })).await {
+            if let Err(_) = docker.kill_container(&self.id, Some(KillContainerOptions { signal: "SIGKILL" })).await {
+                return Err(Error::from("Failed to stop or kill the container"));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn restart(&self, stop: &StopConfig) -> Result<(), Error> {
+        self.graceful_stop(stop).await?;
+        self.start().await
+    }
+
+    /// Archives the server directory into the backup folder, returning the path of the archive
+    pub async fn backup(&self, config: &Config) -> Result<String, Error> {
+        let dir = format!("{}/{}", config.backup_path(), self.name);
+        if let Err(_) = fs::create_dir_all(&dir) {
+            return Err(Error::from("Error creating the backup directory"));
+        }
+        let archive = format!("{dir}/{}-{}.tar.gz", self.name, Utc::now().format("%Y%m%d-%H%M%S"));
+
+        // Keep the server from writing to the world while it's being read, this fails when the
+        // server isn't running which is fine since nothing is writing then either
+        let live = self.send_command(vec!["save-off".to_string()]).await.is_ok();
+        if live {
+            let _ = self.send_command(vec!["save-all".to_string(), "flush".to_string()]).await;
+        }
+
+        let output = tokio::process::Command::new("tar")
+            .arg("-czf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(&self.path)
+            .arg(".")
+            .output()
+            .await;
+
+        if live {
+            let _ = self.send_command(vec!["save-on".to_string()]).await;
+        }
+
+        match output {
+            Ok(o) if o.status.success() => Ok(archive),
+            _ => Err(Error::from("Failed to archive the server directory")),
+        }
+    }
+
     pub async fn status(&self) -> Result<Response, Error> {
         println!("Attempting to get status");
         let hostname = "localhost";
@@ -293,46 +670,178 @@ impl Server {
         }
     }
 
-   pub fn output(&self) -> Result<impl Stream<Item = Result<LogOutput, bollard::errors::Error>>, Error> {
+    pub fn output(&self, query: &LogQuery) -> Result<impl Stream<Item = Result<LogOutput, bollard::errors::Error>>, Error> {
         #[cfg(unix)]
         let docker = if let Ok(d) = Docker::connect_with_socket_defaults() { d } else {
             return Err(Error::from("Couldn't connect to docker on default socket"));
         };
 
+        let since = match &query.since {
+            Some(s) => parse_time(s)?,
+            // Without a starting point only new output is wanted, unless asking for the last few
+            // lines or for everything up to some point
+            None if query.tail.is_none() && query.until.is_none() => Utc::now().timestamp(),
+            None => 0,
+        };
+        let until = match &query.until {
+            Some(u) => parse_time(u)?,
+            None => 0,
+        };
+
         let options = Some(LogsOptions::<String>{
             stdout: true,
-            since: Utc::now().timestamp(),
-            follow: true,
-            ..Default::default()
+            stderr: query.stderr.unwrap_or(false),
+            since,
+            until,
+            follow: query.follow.unwrap_or(true),
+            timestamps: query.timestamps.unwrap_or(false),
+            tail: query.tail.map(|t| t.to_string()).unwrap_or("all".to_string()),
         });
 
         Ok(docker.logs(&self.id, options))
     }
 
-    pub fn clean_output(&self) -> Result<impl Stream<Item = Result<hyper::body::Bytes, bollard::errors::Error>>, Error> {
-        match self.output() {
-            Ok(l) => {
-                Ok(l.filter_map(|msg| {
-                    future::ready(match msg {
-                        Ok(m) => {
-                            // I think these are fine because they should always work
-                            // TODO: Add lazy static crate?
-                            // Add configuability for different matches?
-                            let re1 = Regex::new(r"<.*>.*").unwrap();
-                            let re2 = Regex::new(r"left the game").unwrap();
-                            let re3 = Regex::new(r"joined the game").unwrap();
-                            if re1.is_match(&m.to_string()) || re2.is_match(&m.to_string()) || re3.is_match(&m.to_string()) {
-                                let text = m.to_string().split(" ").skip(3).fold(String::new(), |a, b| format!("{a} {b}")).trim_end_matches("\r\n").to_string();
-                                Some(Ok(Bytes::from(text)))
-                            } else {
-                                None
-                            }
-                                }
-                        Err(_) => None,
-                    })
-                }))
-            },
-            Err(e) => Err(e),
+    /// The output of the server as whole lines
+    pub fn lines(&self, query: &LogQuery) -> Result<impl Stream<Item = String>, Error> {
+        Ok(logs::split_lines(self.output(query)?))
+    }
+
+    /// Parsed events from the output of the server
+    pub fn events(&self, query: &LogQuery) -> Result<impl Stream<Item = LogEvent>, Error> {
+        Ok(self.lines(query)?.filter_map(|l| future::ready(events::parse(&l))))
+    }
+
+    /// The rotated logs the server has written to its logs folder
+    pub fn log_files(&self) -> Result<Vec<LogFile>, Error> {
+        let dir = if let Ok(d) = fs::read_dir(format!("{}/logs", self.path)) { d } else {
+            return Err(Error::from("Failed to read the logs directory"));
+        };
+
+        let mut files: Vec<LogFile> = dir
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let meta = e.metadata().ok().filter(|m| m.is_file())?;
+                Some(LogFile {
+                    name: e.file_name().to_string_lossy().to_string(),
+                    size: meta.len(),
+                    modified: meta.modified().ok().map(DateTime::<Utc>::from),
+                })
+            })
+            .collect();
+        files.sort_by_key(|f| std::cmp::Reverse(f.modified));
+        Ok(files)
+    }
+
+    /// Reads one of the files in the logs folder, unpacking it if it has been gzipped
+    pub async fn read_log(&self, file: &str) -> Result<String, Error> {
+        if file.contains('/') || file.contains('\\') || file.starts_with('.') {
+            return Err(Error::from("Invalid log file name"));
+        }
+
+        let path = format!("{}/logs/{file}", self.path);
+        let gzipped = file.ends_with(".gz");
+        // Old logs can be big, so they're read off the async threads
+        let read = tokio::task::spawn_blocking(move || {
+            let mut text = String::new();
+            let read = if gzipped {
+                fs::File::open(&path).and_then(|f| GzDecoder::new(f).read_to_string(&mut text))
+            } else {
+                fs::File::open(&path).and_then(|mut f| f.read_to_string(&mut text))
+            };
+            read.map(|_| text)
+        }).await;
+
+        match read {
+            Ok(Ok(text)) => Ok(text),
+            _ => Err(Error::from("Failed to read the log file")),
+        }
+    }
+
+    pub fn clean_output(&self, query: &LogQuery) -> Result<impl Stream<Item = Result<hyper::body::Bytes, bollard::errors::Error>>, Error> {
+        Ok(self.events(query)?.filter_map(|e| {
+            future::ready(e.event.chat_line().map(|text| Ok(Bytes::from(text))))
+        }))
+    }
+}
+
+/// Connects to docker for the tasks that watch every server
+pub(crate) fn docker() -> Result<Docker, Error> {
+    #[cfg(unix)]
+    let docker = if let Ok(d) = Docker::connect_with_socket_defaults() { d } else {
+        return Err(Error::from("Couldn't connect to docker on default socket"));
+    };
+    Ok(docker)
+}
+
+/// Which part of the container output to get, times are unix timestamps or RFC 3339
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct LogQuery {
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub tail: Option<u64>,
+    pub follow: Option<bool>,
+    pub stderr: Option<bool>,
+    pub timestamps: Option<bool>,
+}
+
+impl LogQuery {
+    /// Whether this only asks for what the server prints from now on, which can be shared
+    pub fn is_live(&self) -> bool {
+        self.since.is_none()
+            && self.until.is_none()
+            && self.tail.is_none()
+            && self.follow.unwrap_or(true)
+            && !self.stderr.unwrap_or(false)
+            && !self.timestamps.unwrap_or(false)
+    }
+}
+
+fn parse_time(time: &str) -> Result<i64, Error> {
+    if let Ok(t) = time.parse::<i64>() {
+        Ok(t)
+    } else if let Ok(t) = DateTime::parse_from_rfc3339(time) {
+        Ok(t.timestamp())
+    } else {
+        Err(Error::from("Couldn't parse time, use a unix timestamp or RFC 3339"))
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct LogFile {
+    pub name: String,
+    pub size: u64,
+    pub modified: Option<DateTime<Utc>>,
+}
+
+/// How a server is brought down by a graceful stop
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct StopConfig {
+    /// Seconds before the stop at which players are warned
+    pub countdown: Vec<u64>,
+    /// Message broadcast at each mark, `{seconds}` is replaced with the time left
+    pub message: String,
+    /// Seconds to wait for the server to exit on its own after `stop`
+    pub timeout: u64,
+    /// Seconds docker waits after SIGTERM before it kills the container
+    pub grace: u64,
+}
+
+impl StopConfig {
+    /// The most a graceful stop can take, from the first warning to docker giving up on it
+    pub fn longest(&self) -> Duration {
+        let countdown = self.countdown.iter().max().copied().unwrap_or(0);
+        Duration::from_secs(countdown + self.timeout + self.grace)
+    }
+}
+
+impl Default for StopConfig {
+    fn default() -> StopConfig {
+        StopConfig {
+            countdown: vec![30, 10, 5, 4, 3, 2, 1],
+            message: "Server stopping in {seconds} seconds".to_string(),
+            timeout: 60,
+            grace: 10,
         }
     }
 }
@@ -403,6 +912,22 @@ impl ServerConfig {
 }
 */
 
+/// Puts the limits into the compose service, keeping the JVM heap inside the memory limit
+fn apply_limits(mc: &mut Mc, limits: &Limits) -> Result<(), Error> {
+    limits.validate()?;
+    let heap = limits.heap()?;
+    mc.mem_limit = limits.memory.clone();
+    // No swap, a server paging its heap out is worse than one that gets killed
+    mc.memswap_limit = limits.memory.clone();
+    mc.cpus = limits.cpus;
+    mc.cpu_shares = limits.cpu_shares;
+    mc.pids_limit = limits.pids;
+    if heap.is_some() {
+        mc.environment.MEMORY = heap;
+    }
+    Ok(())
+}
+
 // Sketch thing for yaml
 #[derive(Serialize, Deserialize, Debug)]
 struct Compose {
@@ -424,6 +949,16 @@ struct Mc {
     stdin_open: bool,
     restart: String,
     volumes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mem_limit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memswap_limit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cpus: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cpu_shares: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pids_limit: Option<i64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -441,4 +976,24 @@ struct Env {
     SEED: Option<String>,
     MODE: Option<String>,
     CUSTOM_SERVER: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    MEMORY: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    LEVEL: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    FORGE_VERSION: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    NEOFORGE_VERSION: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    FABRIC_LOADER_VERSION: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    QUILT_LOADER_VERSION: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    PAPER_BUILD: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    PURPUR_BUILD: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    RCON_PASSWORD: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    RCON_PORT: Option<u16>,
 }
@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{interval, Duration};
+use crate::{Servers, Events, States};
+use crate::events::{Event, Lifecycle};
+use crate::server::Server;
+use crate::players;
+
+// How often the roster is checked against what the server itself says
+const RECONCILE_EVERY: u64 = 60;
+
+lazy_static! {
+    static ref COLOUR: Regex = Regex::new(r"§.").unwrap();
+    // Steve (069a79f4-44e9-4726-a5be-fca90e38aaf5)
+    static ref LIST_UUIDS: Regex = Regex::new(r"([A-Za-z0-9_]{1,16}) \(([0-9a-fA-F-]{32,36})\)").unwrap();
+}
+
+/// Everything we know about one player on a server, stored with the server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayerRecord {
+    pub name: String,
+    pub uuid: Option<String>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Seconds, not counting the session they're in now
+    pub playtime: i64,
+    pub sessions: u32,
+    /// When the current session started, if they're online
+    pub online_since: Option<DateTime<Utc>>,
+}
+
+impl PlayerRecord {
+    fn join(&mut self, now: DateTime<Utc>) {
+        if self.online_since.is_none() {
+            self.online_since = Some(now);
+            self.sessions += 1;
+        }
+        self.last_seen = now;
+    }
+
+    fn leave(&mut self, now: DateTime<Utc>) {
+        if let Some(since) = self.online_since.take() {
+            self.playtime += (now - since).num_seconds().max(0);
+        }
+        self.last_seen = now;
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OnlinePlayer {
+    pub name: String,
+    pub uuid: Option<String>,
+    pub since: DateTime<Utc>,
+    pub session: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Roster {
+    pub online: Vec<OnlinePlayer>,
+    /// Everyone who has ever played, with playtime including the current session
+    pub players: Vec<PlayerRecord>,
+}
+
+fn file(server: &Server) -> String {
+    format!("{}/mc-docker/players.json", server.path)
+}
+
+/// Players and their sessions on every server, kept in memory and written back on every change
+#[derive(Default)]
+pub struct SessionTracker {
+    // server -> lowercase name -> record
+    servers: Mutex<HashMap<String, HashMap<String, PlayerRecord>>>,
+    // Numbers every change, so a slow write never puts older sessions over newer ones
+    changes: AtomicU64,
+    // server -> the last change written to its file
+    written: tokio::sync::Mutex<HashMap<String, u64>>,
+}
+
+impl SessionTracker {
+    pub fn new() -> SessionTracker {
+        SessionTracker::default()
+    }
+
+    async fn load(server: &Server) -> HashMap<String, PlayerRecord> {
+        let mut players: HashMap<String, PlayerRecord> = tokio::fs::read_to_string(file(server)).await.ok()
+            .and_then(|f| serde_json::from_str::<Vec<PlayerRecord>>(&f).ok())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|p| (p.name.to_lowercase(), p))
+            .collect();
+        // mc-docker went down with these still open, they ended somewhere after they were last seen
+        for p in players.values_mut() {
+            let seen = p.last_seen;
+            p.leave(seen);
+        }
+        players
+    }
+
+    async fn save(&self, server: &Server, change: u64, json: String) {
+        let mut written = self.written.lock().await;
+        if written.get(&server.name).map(|w| *w > change).unwrap_or(false) {
+            return;
+        }
+        let dir = format!("{}/mc-docker", server.path);
+        if let Err(_) = tokio::fs::create_dir_all(&dir).await {
+            println!("Error creating {dir} for the player sessions");
+            return;
+        }
+        if let Err(_) = tokio::fs::write(file(server), json).await {
+            println!("Error writing player sessions of {}", server.name);
+            return;
+        }
+        written.insert(server.name.clone(), change);
+    }
+
+    /// Reads the players of a server from its file the first time they're needed
+    async fn loaded(&self, server: &Server) {
+        if self.servers.lock().unwrap().contains_key(&server.name) {
+            return;
+        }
+        let players = SessionTracker::load(server).await;
+        self.servers.lock().unwrap().entry(server.name.clone()).or_insert(players);
+    }
+
+    /// Changes the players of a server, saving them afterwards. The file is written once the
+    /// lock is let go of, so nobody waits on the disk to get at the roster
+    async fn update<T>(&self, server: &Server, f: impl FnOnce(&mut HashMap<String, PlayerRecord>) -> T) -> T {
+        self.loaded(server).await;
+        let (result, change, json) = {
+            let mut servers = self.servers.lock().unwrap();
+            let players = servers.entry(server.name.clone()).or_default();
+            let result = f(players);
+            let records: Vec<&PlayerRecord> = players.values().collect();
+            (result, self.changes.fetch_add(1, Ordering::SeqCst), serde_json::to_string_pretty(&records))
+        };
+        if let Ok(json) = json {
+            self.save(server, change, json).await;
+        }
+        result
+    }
+
+    pub async fn roster(&self, server: &Server) -> Roster {
+        self.loaded(server).await;
+        let now = Utc::now();
+        let mut servers = self.servers.lock().unwrap();
+        let players = servers.entry(server.name.clone()).or_default();
+
+        let mut online: Vec<OnlinePlayer> = players.values()
+            .filter_map(|p| Some(OnlinePlayer {
+                name: p.name.clone(),
+                uuid: p.uuid.clone(),
+                since: p.online_since?,
+                session: (now - p.online_since?).num_seconds(),
+            }))
+            .collect();
+        online.sort_by_key(|a| a.since);
+
+        let mut players: Vec<PlayerRecord> = players.values().cloned()
+            .map(|mut p| {
+                if let Some(since) = p.online_since {
+                    p.playtime += (now - since).num_seconds().max(0);
+                }
+                p
+            })
+            .collect();
+        players.sort_by_key(|p| std::cmp::Reverse(p.last_seen));
+
+        Roster { online, players }
+    }
+
+    async fn join(&self, server: &Server, name: &str, uuid: Option<String>) {
+        let now = Utc::now();
+        self.update(server, |players| {
+            let p = players.entry(name.to_lowercase()).or_insert_with(|| PlayerRecord {
+                name: name.to_string(),
+                uuid: None,
+                first_seen: now,
+                last_seen: now,
+                playtime: 0,
+                sessions: 0,
+                online_since: None,
+            });
+            if uuid.is_some() {
+                p.uuid = uuid;
+            }
+            p.join(now);
+        }).await;
+    }
+
+    async fn leave(&self, server: &Server, name: &str) {
+        let now = Utc::now();
+        self.update(server, |players| {
+            if let Some(p) = players.get_mut(&name.to_lowercase()) {
+                p.leave(now);
+            }
+        }).await;
+    }
+
+    async fn leave_all(&self, server: &Server) {
+        let now = Utc::now();
+        self.update(server, |players| players.values_mut().for_each(|p| p.leave(now))).await;
+    }
+
+    /// Makes the roster match the players the server says are online
+    async fn reconcile(&self, server: &Server, online: Vec<(String, Option<String>)>) {
+        let now = Utc::now();
+        self.update(server, |players| {
+            for p in players.values_mut().filter(|p| p.online_since.is_some()) {
+                if online.iter().any(|(name, _)| name.eq_ignore_ascii_case(&p.name)) {
+                    p.last_seen = now;
+                } else {
+                    p.leave(now);
+                }
+            }
+            for (name, uuid) in online {
+                let p = players.entry(name.to_lowercase()).or_insert_with(|| PlayerRecord {
+                    name: name.clone(),
+                    uuid: None,
+                    first_seen: now,
+                    last_seen: now,
+                    playtime: 0,
+                    sessions: 0,
+                    online_since: None,
+                });
+                if uuid.is_some() {
+                    p.uuid = uuid;
+                }
+                p.join(now);
+            }
+        }).await;
+    }
+}
+
+/// Who the server says is online, from `list uuids` or plain `list` on versions without it
+async fn online(server: &Server) -> Option<Vec<(String, Option<String>)>> {
+    let out = server.send_command(vec!["list".to_string(), "uuids".to_string()]).await.ok()?;
+    let out = COLOUR.replace_all(&out, "").to_string();
+    if LIST_UUIDS.is_match(&out) {
+        return Some(LIST_UUIDS.captures_iter(&out).map(|c| (c[1].to_string(), Some(c[2].to_string()))).collect());
+    }
+
+    let out = server.send_command(vec!["list".to_string()]).await.ok()?;
+    let out = COLOUR.replace_all(&out, "").to_string();
+    // There are 1 of a max of 20 players online: Steve
+    let (_, names) = out.split_once(':')?;
+    Some(names.split(',')
+        .map(|n| n.trim().to_string())
+        .filter(|n| !n.is_empty())
+        .map(|n| (n, None))
+        .collect())
+}
+
+/// Follows joins and leaves on every server, checking the roster against the server every so often
+pub async fn track(servers: Servers, states: States, events: Events, sessions: Arc<SessionTracker>) {
+    let (_, mut rx) = events.subscribe(None);
+    let mut timer = interval(Duration::from_secs(RECONCILE_EVERY));
+
+    loop {
+        tokio::select! {
+            envelope = rx.recv() => {
+                let envelope = match envelope {
+                    Ok(e) => e,
+                    // The next reconcile fixes whatever was missed
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return,
+                };
+                let server = if let Some(s) = servers.read().await.get(&envelope.server) { s.clone() } else { continue };
+                match envelope.event.event {
+                    Event::Join { player } => {
+                        let (s, p) = (server.clone(), player.clone());
+                        let uuid = tokio::task::spawn_blocking(move || players::lookup(&s, &p)).await
+                            .ok().and_then(|l| l.ok()).map(|(uuid, _)| uuid);
+                        sessions.join(&server, &player, uuid).await;
+                    },
+                    Event::Leave { player } => sessions.leave(&server, &player).await,
+                    Event::Lifecycle { state: Lifecycle::Stopped | Lifecycle::Crashed } => sessions.leave_all(&server).await,
+                    _ => {},
+                }
+            },
+            _ = timer.tick() => {
+                let running: Vec<Server> = servers.read().await.values()
+                    .filter(|s| states.was_running(&s.name))
+                    .cloned()
+                    .collect();
+                for server in running {
+                    // Still starting up, or the server has no RCON to ask
+                    if let Some(online) = online(&server).await {
+                        sessions.reconcile(&server, online).await;
+                    }
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use bollard::container::{Stats, StatsOptions, MemoryStatsStats};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use serde::Serialize;
+use tokio::time::{sleep, Duration};
+use crate::Servers;
+use crate::server::docker;
+
+// One sample is kept every this many seconds, for an hour
+const SAMPLE_EVERY: i64 = 10;
+const HISTORY: usize = 360;
+
+/// What a server's container is using, the network and block io are totals since it started
+#[derive(Serialize, Debug, Clone)]
+pub struct Resources {
+    pub time: DateTime<Utc>,
+    pub cpu_percent: f64,
+    pub memory_usage: u64,
+    pub memory_limit: u64,
+    pub memory_percent: f64,
+    pub net_rx: u64,
+    pub net_tx: u64,
+    pub block_read: u64,
+    pub block_write: u64,
+    pub pids: Option<u64>,
+}
+
+impl Resources {
+    fn from(stats: &Stats) -> Resources {
+        let cpu_delta = stats.cpu_stats.cpu_usage.total_usage as f64
+            - stats.precpu_stats.cpu_usage.total_usage as f64;
+        let system_delta = stats.cpu_stats.system_cpu_usage.unwrap_or(0) as f64
+            - stats.precpu_stats.system_cpu_usage.unwrap_or(0) as f64;
+        let cpus = stats.cpu_stats.online_cpus
+            .or(stats.cpu_stats.cpu_usage.percpu_usage.as_ref().map(|p| p.len() as u64))
+            .unwrap_or(1) as f64;
+        let cpu_percent = if cpu_delta > 0.0 && system_delta > 0.0 {
+            cpu_delta / system_delta * cpus * 100.0
+        } else {
+            0.0
+        };
+
+        // Same as `docker stats`, page cache doesn't count as used
+        let cache = match &stats.memory_stats.stats {
+            Some(MemoryStatsStats::V1(v1)) => v1.total_inactive_file,
+            Some(MemoryStatsStats::V2(v2)) => v2.inactive_file,
+            None => 0,
+        };
+        let memory_usage = stats.memory_stats.usage.unwrap_or(0).saturating_sub(cache);
+        let memory_limit = stats.memory_stats.limit.unwrap_or(0);
+        let memory_percent = if memory_limit > 0 {
+            memory_usage as f64 / memory_limit as f64 * 100.0
+        } else {
+            0.0
+        };
+
+        let (net_rx, net_tx) = stats.networks.as_ref()
+            .map(|n| n.values().fold((0, 0), |(rx, tx), net| (rx + net.rx_bytes, tx + net.tx_bytes)))
+            .unwrap_or((0, 0));
+
+        let (block_read, block_write) = stats.blkio_stats.io_service_bytes_recursive.as_ref()
+            .map(|entries| entries.iter().fold((0, 0), |(r, w), e| match e.op.to_lowercase().as_str() {
+                "read" => (r + e.value, w),
+                "write" => (r, w + e.value),
+                _ => (r, w),
+            }))
+            .unwrap_or((0, 0));
+
+        Resources {
+            time: Utc::now(),
+            cpu_percent,
+            memory_usage,
+            memory_limit,
+            memory_percent,
+            net_rx,
+            net_tx,
+            block_read,
+            block_write,
+            pids: stats.pids_stats.current,
+        }
+    }
+}
+
+/// The latest resource use of every running server, and a rolling history of it
+#[derive(Default)]
+pub struct StatsCollector {
+    current: Mutex<HashMap<String, Resources>>,
+    history: Mutex<HashMap<String, VecDeque<Resources>>>,
+}
+
+impl StatsCollector {
+    pub fn new() -> StatsCollector {
+        StatsCollector::default()
+    }
+
+    pub fn current(&self, name: &str) -> Option<Resources> {
+        self.current.lock().unwrap().get(name).cloned()
+    }
+
+    pub fn history(&self, name: &str) -> Vec<Resources> {
+        self.history.lock().unwrap().get(name).map(|h| h.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    fn record(&self, name: &str, resources: Resources) {
+        let mut history = self.history.lock().unwrap();
+        let history = history.entry(name.to_string()).or_default();
+        let due = history.back().map(|last| (resources.time - last.time).num_seconds() >= SAMPLE_EVERY).unwrap_or(true);
+        if due {
+            if history.len() == HISTORY {
+                history.pop_front();
+            }
+            history.push_back(resources.clone());
+        }
+        self.current.lock().unwrap().insert(name.to_string(), resources);
+    }
+
+    fn clear(&self, name: &str) {
+        self.current.lock().unwrap().remove(name);
+    }
+}
+
+/// Follows the docker stats of a server for as long as it's registered
+pub async fn collect(name: String, servers: Servers, stats: Arc<StatsCollector>) {
+    loop {
+        let server = if let Some(s) = servers.read().await.get(&name) { s.clone() } else {
+            stats.clear(&name);
+            stats.history.lock().unwrap().remove(&name);
+            return;
+        };
+
+        if let Ok(docker) = docker() {
+            let mut stream = docker.stats(&server.id, Some(StatsOptions { stream: true, one_shot: false }));
+            while let Some(Ok(s)) = stream.next().await {
+                // A stopped container still sends the odd empty sample
+                if s.pids_stats.current.unwrap_or(0) == 0 {
+                    break;
+                }
+                stats.record(&name, Resources::from(&s));
+            }
+        }
+
+        // The stream ends when the container stops
+        stats.clear(&name);
+        sleep(Duration::from_secs(10)).await;
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use tokio::time::{sleep, Duration};
+use crate::{Servers, States};
+use crate::server::Server;
+
+// Servers are asked every this many seconds, and an hour of answers is kept
+const POLL_EVERY: u64 = 30;
+const HISTORY: usize = 120;
+
+lazy_static! {
+    static ref COLOUR: Regex = Regex::new(r"§.").unwrap();
+    // Paper: TPS from last 1m, 5m, 15m: 20.0, *20.0, 19.98
+    static ref PAPER_TPS: Regex = Regex::new(r"TPS from last 1m, 5m, 15m:\s*\*?([\d.]+),\s*\*?([\d.]+),\s*\*?([\d.]+)").unwrap();
+    // Paper: avg/min/max for the last 5s, 10s and 1m, the first one is the freshest
+    static ref PAPER_MSPT: Regex = Regex::new(r"([\d.]+)/([\d.]+)/([\d.]+)").unwrap();
+    // Forge: Overall: Mean tick time: 1.234 ms. Mean TPS: 20.000
+    static ref FORGE_OLD: Regex = Regex::new(r"Overall\s*:\s*Mean tick time:\s*([\d.]+)\s*ms\.\s*Mean TPS:\s*([\d.]+)").unwrap();
+    // Newer Forge and NeoForge: Overall: 20.000 TPS (1.234 ms/tick)
+    static ref FORGE_NEW: Regex = Regex::new(r"Overall\s*:\s*([\d.]+)\s*TPS\s*\(([\d.]+)\s*ms/tick\)").unwrap();
+    // Vanilla 1.20.3+ `tick query`
+    static ref VANILLA_RATE: Regex = Regex::new(r"Target tick rate:\s*([\d.]+)").unwrap();
+    static ref VANILLA_MSPT: Regex = Regex::new(r"Average time per tick:\s*([\d.]+)\s*ms").unwrap();
+}
+
+/// How fast a server was ticking at some point
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Tick {
+    pub time: DateTime<Utc>,
+    pub tps: f64,
+    /// Paper also reports the 5 and 15 minute averages
+    pub tps_5m: Option<f64>,
+    pub tps_15m: Option<f64>,
+    pub mspt: Option<f64>,
+}
+
+impl Tick {
+    fn new(tps: f64, mspt: Option<f64>) -> Tick {
+        Tick { time: Utc::now(), tps, tps_5m: None, tps_15m: None, mspt }
+    }
+}
+
+/// The ways servers report their tick performance
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Flavour {
+    Paper,
+    Forge,
+    NeoForge,
+    Vanilla,
+}
+
+impl Flavour {
+    fn of(server_type: &str) -> Flavour {
+        match server_type {
+            "PAPER" | "PURPUR" | "PUFFERFISH" | "FOLIA" | "SPIGOT" | "BUKKIT" => Flavour::Paper,
+            "FORGE" => Flavour::Forge,
+            "NEOFORGE" => Flavour::NeoForge,
+            _ => Flavour::Vanilla,
+        }
+    }
+}
+
+fn num(s: &str) -> Option<f64> {
+    s.parse().ok()
+}
+
+fn parse_paper(tps: &str, mspt: Option<&str>) -> Option<Tick> {
+    let tps = COLOUR.replace_all(tps, "");
+    let c = PAPER_TPS.captures(&tps)?;
+    // Spigot and Bukkit don't have /mspt, those are left without it
+    let mspt = mspt
+        .map(|m| COLOUR.replace_all(m, "").to_string())
+        .and_then(|m| PAPER_MSPT.captures(&m).and_then(|c| num(&c[1])));
+    Some(Tick {
+        tps_5m: num(&c[2]),
+        tps_15m: num(&c[3]),
+        ..Tick::new(num(&c[1])?, mspt)
+    })
+}
+
+fn parse_forge(out: &str) -> Option<Tick> {
+    let out = COLOUR.replace_all(out, "");
+    if let Some(c) = FORGE_NEW.captures(&out) {
+        return Some(Tick::new(num(&c[1])?, num(&c[2])));
+    }
+    let c = FORGE_OLD.captures(&out)?;
+    Some(Tick::new(num(&c[2])?, num(&c[1])))
+}
+
+fn parse_vanilla(out: &str) -> Option<Tick> {
+    let out = COLOUR.replace_all(out, "");
+    let mspt = num(&VANILLA_MSPT.captures(&out)?[1])?;
+    let rate = VANILLA_RATE.captures(&out).and_then(|c| num(&c[1])).unwrap_or(20.0);
+    // A server can't tick faster than it's told to, only slower
+    let tps = if mspt > 0.0 { rate.min(1000.0 / mspt) } else { rate };
+    Some(Tick::new(tps, Some(mspt)))
+}
+
+/// Asks a server how it's doing with whatever command its TYPE understands
+pub async fn query(server: &Server) -> Option<Tick> {
+    let cmd = |c: &str| c.split(' ').map(|s| s.to_string()).collect::<Vec<_>>();
+    match Flavour::of(&server.server_type()) {
+        Flavour::Paper => {
+            let tps = server.send_command(cmd("tps")).await.ok()?;
+            let mspt = server.send_command(cmd("mspt")).await.ok();
+            parse_paper(&tps, mspt.as_deref())
+        },
+        Flavour::Forge => parse_forge(&server.send_command(cmd("forge tps")).await.ok()?),
+        Flavour::NeoForge => parse_forge(&server.send_command(cmd("neoforge tps")).await.ok()?),
+        Flavour::Vanilla => parse_vanilla(&server.send_command(cmd("tick query")).await.ok()?),
+    }
+}
+
+/// The latest tick performance of every running server, and a rolling history of it
+#[derive(Default)]
+pub struct TickMonitor {
+    history: Mutex<HashMap<String, VecDeque<Tick>>>,
+}
+
+impl TickMonitor {
+    pub fn new() -> TickMonitor {
+        TickMonitor::default()
+    }
+
+    pub fn current(&self, name: &str) -> Option<Tick> {
+        self.history.lock().unwrap().get(name).and_then(|h| h.back().cloned())
+    }
+
+    pub fn history(&self, name: &str) -> Vec<Tick> {
+        self.history.lock().unwrap().get(name).map(|h| h.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    fn record(&self, name: &str, tick: Tick) {
+        let mut history = self.history.lock().unwrap();
+        let history = history.entry(name.to_string()).or_default();
+        if history.len() == HISTORY {
+            history.pop_front();
+        }
+        history.push_back(tick);
+    }
+}
+
+/// Polls every running server for its tick performance
+pub async fn poll(servers: Servers, states: States, ticks: Arc<TickMonitor>) {
+    loop {
+        let running: Vec<Server> = servers.read().await.values()
+            .filter(|s| states.was_running(&s.name))
+            .cloned()
+            .collect();
+
+        for server in running {
+            // Servers that are still starting up won't answer yet, they're just tried again next time
+            if let Some(tick) = query(&server).await {
+                ticks.record(&server.name, tick);
+            }
+        }
+
+        // Forget servers that were removed
+        {
+            let names = servers.read().await;
+            ticks.history.lock().unwrap().retain(|name, _| names.contains_key(name));
+        }
+
+        sleep(Duration::from_secs(POLL_EVERY)).await;
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use bollard::container::InspectContainerOptions;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{timeout, Duration, Instant};
+use crate::{Config, Events, States};
+use crate::error::Error;
+use crate::events::{Event, Lifecycle};
+use crate::server::{docker, Server};
+
+// Modded servers can take a long while on their first start after an upgrade
+const DEFAULT_TIMEOUT: u64 = 600;
+
+lazy_static! {
+    // name -> the last upgrade of that server
+    static ref UPGRADES: Mutex<HashMap<String, UpgradeStatus>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Upgrade {
+    pub version: String,
+    /// Forge/NeoForge/Fabric/Quilt loader or Paper/Purpur build, picked by the image when left out
+    pub loader: Option<String>,
+    /// Seconds the server gets to finish starting on the new version
+    pub timeout: Option<u64>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    BackingUp,
+    Stopping,
+    Recreating,
+    Starting,
+    Done,
+    RollingBack,
+    RolledBack,
+    Failed,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct UpgradeStatus {
+    pub from: String,
+    pub to: String,
+    pub stage: Stage,
+    pub backup: Option<String>,
+    pub message: Option<String>,
+    pub started: DateTime<Utc>,
+    pub finished: Option<DateTime<Utc>>,
+}
+
+impl UpgradeStatus {
+    fn running(&self) -> bool {
+        self.finished.is_none()
+    }
+}
+
+pub fn status(name: &str) -> Option<UpgradeStatus> {
+    UPGRADES.lock().unwrap().get(name).cloned()
+}
+
+fn set(name: &str, f: impl FnOnce(&mut UpgradeStatus)) {
+    if let Some(s) = UPGRADES.lock().unwrap().get_mut(name) {
+        f(s);
+        println!("Upgrade of {name}: {:?}", s.stage);
+    }
+}
+
+fn stage(name: &str, stage: Stage) {
+    set(name, |s| s.stage = stage);
+}
+
+/// Checks the upgrade can go ahead and marks it as started, the rest happens in `run`
+pub fn begin(server: &Server, upgrade: &Upgrade) -> Result<UpgradeStatus, Error> {
+    let version = upgrade.version.trim();
+    if version.is_empty() || version.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(Error::from("Not a valid version"));
+    }
+    let (from, _) = server.versions()?;
+
+    let mut upgrades = UPGRADES.lock().unwrap();
+    if upgrades.get(&server.name).map(|u| u.running()).unwrap_or(false) {
+        return Err(Error::from("This server is already being upgraded"));
+    }
+    let status = UpgradeStatus {
+        from,
+        to: version.to_string(),
+        stage: Stage::BackingUp,
+        backup: None,
+        message: None,
+        started: Utc::now(),
+        finished: None,
+    };
+    upgrades.insert(server.name.clone(), status.clone());
+    Ok(status)
+}
+
+/// Backs up, stops, moves the server to the new version and starts it again, putting everything
+/// back the way it was if it doesn't come up
+pub async fn run(server: Server, upgrade: Upgrade, config: Config, states: States, events: Events) {
+    let name = server.name.clone();
+    let (old_version, old_loader) = match server.versions() {
+        Ok(v) => v,
+        Err(e) => return fail(&name, e),
+    };
+
+    let backup = match server.backup(&config).await {
+        Ok(b) => b,
+        Err(e) => return fail(&name, e),
+    };
+    set(&name, |s| s.backup = Some(backup.clone()));
+
+    stage(&name, Stage::Stopping);
+    if states.is_running(&server).await {
+        states.expect_stop(&name, &config.stop);
+        let stopped = server.graceful_stop(&config.stop).await;
+        states.stop_done(&name);
+        if let Err(e) = stopped {
+            return fail(&name, e);
+        }
+    }
+
+    stage(&name, Stage::Recreating);
+    if let Err(e) = server.set_versions(upgrade.version.trim(), upgrade.loader.clone()) {
+        return fail(&name, e);
+    }
+
+    if let Err(e) = server.compose_up(true).await {
+        return rollback(&server, &config, &states, &backup, &old_version, old_loader, e).await;
+    }
+    // Only listening once the old container is gone so its exit can't count against the new one,
+    // a Started line that slips past in between is caught by the ping at the end
+    let (_, mut rx) = events.subscribe(None);
+    let container = match container(&server).await {
+        Some((id, true)) => id,
+        _ => {
+            let e = Error::from("The new container isn't running");
+            return rollback(&server, &config, &states, &backup, &old_version, old_loader, e).await;
+        },
+    };
+
+    stage(&name, Stage::Starting);
+    let deadline = Instant::now() + Duration::from_secs(upgrade.timeout.unwrap_or(DEFAULT_TIMEOUT));
+    let started = loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match timeout(left, rx.recv()).await {
+            Ok(Ok(e)) if e.server == name => match e.event.event {
+                Event::Started { .. } => break Ok(()),
+                Event::Crash { .. } => break Err(Error::from("The server crashed while starting")),
+                // Events only name the server, docker knows whether it was the new container
+                Event::Lifecycle { state: Lifecycle::Stopped | Lifecycle::Crashed } if !still_up(&server, &container).await =>
+                    break Err(Error::from("The server went down while starting")),
+                _ => continue,
+            },
+            Ok(Ok(_)) => continue,
+            Ok(Err(RecvError::Lagged(_))) => {
+                // Whatever got missed, the container and the server itself can tell how far it got
+                if !still_up(&server, &container).await {
+                    break Err(Error::from("The server went down while starting"));
+                }
+                if server.status().await.is_ok() {
+                    break Ok(());
+                }
+            },
+            Ok(Err(RecvError::Closed)) => break Err(Error::from("Lost the server's events")),
+            Err(_) if server.status().await.is_ok() => break Ok(()),
+            Err(_) => break Err(Error::from("The server didn't finish starting in time")),
+        }
+    };
+
+    match started {
+        Ok(_) => set(&name, |s| {
+            s.stage = Stage::Done;
+            s.finished = Some(Utc::now());
+        }),
+        Err(e) => rollback(&server, &config, &states, &backup, &old_version, old_loader, e).await,
+    }
+}
+
+/// The id of the server's container and whether it's running
+async fn container(server: &Server) -> Option<(String, bool)> {
+    let docker = docker().ok()?;
+    let info = docker.inspect_container(&server.id, None::<InspectContainerOptions>).await.ok()?;
+    Some((info.id.unwrap_or_default(), info.state.and_then(|s| s.running).unwrap_or(false)))
+}
+
+async fn still_up(server: &Server, id: &str) -> bool {
+    matches!(container(server).await, Some((current, true)) if current == id)
+}
+
+fn fail(name: &str, e: Error) {
+    println!("Upgrade of {name} failed: {:?}", e);
+    set(name, |s| {
+        s.stage = Stage::Failed;
+        s.message = Some(format!("{:?}", e));
+        s.finished = Some(Utc::now());
+    });
+}
+
+async fn rollback(server: &Server, config: &Config, states: &States, backup: &str, version: &str, loader: Option<String>, reason: Error) {
+    let name = &server.name;
+    println!("Upgrade of {name} failed, rolling back: {:?}", reason);
+    set(name, |s| {
+        s.stage = Stage::RollingBack;
+        s.message = Some(format!("{:?}", reason));
+    });
+
+    if states.is_running(server).await {
+        states.expect_stop(name, &config.stop);
+        let stopped = server.graceful_stop(&config.stop).await;
+        states.stop_done(name);
+        if let Err(e) = stopped {
+            return fail(name, e);
+        }
+    }
+
+    if let Err(e) = restore(server, backup).await {
+        return fail(name, e);
+    }
+    // The backup has the old compose file, this is for when it was taken before a manual edit
+    if let Err(e) = server.set_versions(version, loader) {
+        return fail(name, e);
+    }
+    if let Err(e) = server.compose_up(true).await {
+        return fail(name, e);
+    }
+
+    set(name, |s| {
+        s.stage = Stage::RolledBack;
+        s.finished = Some(Utc::now());
+    });
+}
+
+/// Puts the server directory back to how it is in the backup
+async fn restore(server: &Server, backup: &str) -> Result<(), Error> {
+    let dir = if let Ok(d) = fs::read_dir(&server.path) { d } else {
+        return Err(Error::from("Failed to read the server directory"));
+    };
+    // Whatever the new version wrote goes, apart from mc-docker's own records and the backup itself
+    // if it happens to live in here
+    for entry in dir.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if entry.file_name() == "mc-docker" || Path::new(backup).starts_with(&path) {
+            continue;
+        }
+        let removed = if path.is_dir() && !path.is_symlink() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+        if let Err(_) = removed {
+            return Err(Error::from("Failed to clear the server directory"));
+        }
+    }
+
+    let output = tokio::process::Command::new("tar")
+        .arg("-xzf")
+        .arg(backup)
+        .arg("-C")
+        .arg(&server.path)
+        .output()
+        .await;
+    match output {
+        Ok(o) if o.status.success() => Ok(()),
+        _ => Err(Error::from("Failed to extract the backup")),
+    }
+}
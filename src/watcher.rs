@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use bollard::{
+    Docker,
+    container::InspectContainerOptions,
+    errors::Error::DockerResponseServerError,
+    system::EventsOptions,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use futures::StreamExt;
+use serde::Serialize;
+use tokio::time::{sleep, Duration, Instant};
+use crate::{Servers, Events};
+use crate::events::{LogEvent, Lifecycle};
+use crate::server::{docker, Server, StopConfig};
+
+// Seconds after a stop is over that its exit can take to come through
+const STOP_SETTLE: u64 = 10;
+
+/// What docker last told us about the container of a server
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ServerState {
+    pub status: String,
+    pub running: bool,
+    pub exit_code: Option<i64>,
+    pub oom_killed: bool,
+    pub since: Option<DateTime<Utc>>,
+    pub started: Option<DateTime<Utc>>,
+    pub restarts: u32,
+    // Until when an exit counts as mc-docker stopping the container itself rather than a crash
+    #[serde(skip)]
+    expected_stop: Option<Instant>,
+    // Set when the server logged a crash, so even a clean exit afterwards counts as one
+    #[serde(skip)]
+    logged_crash: bool,
+}
+
+/// Keeps the state of every registered server up to date from the docker events stream
+#[derive(Default)]
+pub struct StateTracker {
+    states: Mutex<HashMap<String, ServerState>>,
+}
+
+impl StateTracker {
+    pub fn new() -> StateTracker {
+        StateTracker::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<ServerState> {
+        self.states.lock().unwrap().get(name).cloned()
+    }
+
+    /// What docker last said, for the samplers where getting it wrong only skips a sample
+    pub fn was_running(&self, name: &str) -> bool {
+        self.get(name).map(|s| s.running).unwrap_or(false)
+    }
+
+    /// Whether the container of a server is running, asking docker when nothing is known about it
+    /// yet. Anything that acts on a stopped server goes through here
+    pub async fn is_running(&self, server: &Server) -> bool {
+        match self.get(&server.name) {
+            Some(s) => s.running,
+            None => self.inspect(server).await,
+        }
+    }
+
+    /// Reads the state of a server's container straight from docker, counting it as running when
+    /// docker can't say
+    pub async fn inspect(&self, server: &Server) -> bool {
+        let known = match docker() {
+            Ok(d) => refresh(&d, self, &server.name, &server.id).await,
+            Err(_) => false,
+        };
+        if !known {
+            println!("Couldn't get the state of {} from docker, treating it as running", server.name);
+            return true;
+        }
+        self.was_running(&server.name)
+    }
+
+    /// Marks the server going down as intended for as long as a stop could take
+    pub fn expect_stop(&self, name: &str, stop: &StopConfig) {
+        self.update(name, |s| s.expected_stop = Some(Instant::now() + stop.longest()));
+    }
+
+    /// Called once a stop is over, whether it worked or not. The docker events it caused can still
+    /// be on their way, so it takes a few more seconds until exits count as crashes again
+    pub fn stop_done(&self, name: &str) {
+        let until = Instant::now() + Duration::from_secs(STOP_SETTLE);
+        self.update(name, |s| s.expected_stop = s.expected_stop.map(|e| e.min(until)));
+    }
+
+    /// Marks the next time the server goes down as a crash
+    pub fn note_crash(&self, name: &str) {
+        self.states.lock().unwrap().entry(name.to_string()).or_default().logged_crash = true;
+    }
+
+    fn update(&self, name: &str, f: impl FnOnce(&mut ServerState)) -> ServerState {
+        let mut states = self.states.lock().unwrap();
+        let state = states.entry(name.to_string()).or_default();
+        f(state);
+        state.clone()
+    }
+}
+
+/// Follows docker events for the containers of registered servers, updating their state and
+/// publishing lifecycle events whenever one of them starts or goes down
+pub async fn watch(servers: Servers, states: Arc<StateTracker>, events: Events) {
+    loop {
+        let docker = if let Ok(d) = docker() { d } else {
+            println!("Couldn't connect to docker to watch for events, trying again soon");
+            sleep(Duration::from_secs(10)).await;
+            continue;
+        };
+
+        // Catch up on anything that happened while we weren't listening
+        let names: Vec<(String, String)> = servers.read().await.values().map(|s| (s.name.clone(), s.id.clone())).collect();
+        for (name, id) in names {
+            refresh(&docker, &states, &name, &id).await;
+        }
+
+        let mut filters = HashMap::new();
+        filters.insert("type".to_string(), vec!["container".to_string()]);
+        let mut stream = docker.events(Some(EventsOptions::<String> { filters, ..Default::default() }));
+
+        while let Some(Ok(msg)) = stream.next().await {
+            let (action, actor) = match (msg.action, msg.actor) {
+                (Some(a), Some(b)) => (a, b),
+                _ => continue,
+            };
+            let container_id = actor.id.unwrap_or_default();
+            let attributes = actor.attributes.unwrap_or_default();
+            let container_name = attributes.get("name").cloned().unwrap_or_default();
+
+            // Servers know their container by the name compose gave it
+            let name = servers.read().await.values()
+                .find(|s| s.id == container_name || (!s.id.is_empty() && container_id.starts_with(&s.id)))
+                .map(|s| s.name.clone());
+            let name = if let Some(n) = name { n } else { continue };
+
+            let time = msg.time.and_then(|t| Utc.timestamp_opt(t, 0).single());
+            let lifecycle = match action.as_str() {
+                "start" => {
+                    states.update(&name, |s| {
+                        s.status = "running".to_string();
+                        s.running = true;
+                        s.exit_code = None;
+                        s.oom_killed = false;
+                        s.logged_crash = false;
+                        s.since = time;
+                        s.started = time;
+                    });
+                    Some(Lifecycle::Starting)
+                },
+                "oom" => {
+                    states.update(&name, |s| s.oom_killed = true);
+                    None
+                },
+                "restart" => {
+                    states.update(&name, |s| s.restarts += 1);
+                    None
+                },
+                "die" => {
+                    let code = attributes.get("exitCode").and_then(|c| c.parse::<i64>().ok());
+                    states.update(&name, |s| {
+                        s.status = "exited".to_string();
+                        s.running = false;
+                        s.exit_code = code;
+                        s.since = time;
+                    });
+                    // Inspect knows about OOM kills even when the oom event didn't come through
+                    refresh(&docker, &states, &name, &container_id).await;
+                    let state = states.get(&name).unwrap_or_default();
+                    states.update(&name, |s| s.logged_crash = false);
+                    let expected = state.expected_stop.map(|e| Instant::now() < e).unwrap_or(false);
+                    let crashed = !expected
+                        && (state.oom_killed || state.logged_crash || state.exit_code.unwrap_or(0) != 0);
+                    Some(if crashed { Lifecycle::Crashed } else { Lifecycle::Stopped })
+                },
+                _ => None,
+            };
+
+            if let Some(l) = lifecycle {
+                println!("{name} is now {:?}", l);
+                events.publish(&name, LogEvent::lifecycle(l));
+            }
+        }
+
+        println!("Docker events stream ended, reconnecting");
+        sleep(Duration::from_secs(5)).await;
+    }
+}
+
+/// Reads the current state of a container straight from docker, false when it couldn't
+async fn refresh(docker: &Docker, states: &StateTracker, name: &str, id: &str) -> bool {
+    let state = match docker.inspect_container(id, None::<InspectContainerOptions>).await {
+        Ok(i) => i.state,
+        // No container at all, so nothing is running either
+        Err(DockerResponseServerError { status_code: 404, .. }) => {
+            states.update(name, |s| {
+                s.status = "missing".to_string();
+                s.running = false;
+            });
+            return true;
+        },
+        Err(_) => return false,
+    };
+
+    let st = if let Some(st) = state { st } else { return false };
+    states.update(name, |s| {
+        s.status = st.status.map(|status| status.to_string()).unwrap_or_default();
+        s.running = st.running.unwrap_or(false);
+        s.exit_code = st.exit_code;
+        s.oom_killed = st.oom_killed.unwrap_or(false);
+        s.started = st.started_at
+            .and_then(|t| DateTime::parse_from_rfc3339(&t).ok())
+            .map(|t| t.with_timezone(&Utc));
+        s.since = if s.running { s.started } else {
+            st.finished_at
+                .and_then(|t| DateTime::parse_from_rfc3339(&t).ok())
+                .map(|t| t.with_timezone(&Utc))
+        };
+    });
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(name: &str) -> Server {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "id": format!("mc-docker-test-{name}"),
+            "path": "/nonexistent",
+            "port": 25565,
+        })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn known_state_is_used() {
+        let states = StateTracker::new();
+        states.update("up", |s| s.running = true);
+        states.update("down", |s| s.running = false);
+        assert!(states.is_running(&server("up")).await);
+        assert!(!states.is_running(&server("down")).await);
+        assert!(!states.was_running("unknown"));
+    }
+}
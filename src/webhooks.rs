@@ -0,0 +1,305 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{sleep, Duration};
+use crate::bus::Envelope;
+use crate::events::Event;
+use crate::{Config, Events};
+
+// Deliveries kept around for the API
+const LOG_SIZE: usize = 500;
+const ATTEMPTS: u32 = 4;
+
+/// An outside URL that gets sent server events as they happen
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Event types (or lifecycle states) to send, everything when empty
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Servers to send events for, every server when empty
+    #[serde(default)]
+    pub servers: Vec<String>,
+    /// Signs the body with HMAC-SHA256 into the X-Mc-Docker-Signature header
+    pub secret: Option<String>,
+}
+
+impl WebhookConfig {
+    fn wants(&self, envelope: &Envelope) -> bool {
+        let server = self.servers.is_empty() || self.servers.contains(&envelope.server);
+        let event = &envelope.event.event;
+        let kind = self.events.is_empty()
+            || self.events.iter().any(|e| e == event.kind())
+            || match event {
+                Event::Lifecycle { state } => self.events.iter().any(|e| Some(e.as_str()) == state_name(state).as_deref()),
+                _ => false,
+            };
+        server && kind
+    }
+}
+
+fn state_name<T: Serialize>(state: &T) -> Option<String> {
+    serde_json::to_value(state).ok().and_then(|v| v.as_str().map(|s| s.to_string()))
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Delivery {
+    pub event_id: u64,
+    pub server: String,
+    pub event: String,
+    pub url: String,
+    pub time: DateTime<Utc>,
+    pub attempts: u32,
+    pub status: Option<u16>,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+/// Sends events from the bus to every webhook that wants them
+pub struct Dispatcher {
+    hooks: Vec<WebhookConfig>,
+    client: reqwest::Client,
+    log: Mutex<VecDeque<Delivery>>,
+}
+
+impl Dispatcher {
+    pub fn start(config: &Config, events: Events) -> Arc<Dispatcher> {
+        let dispatcher = Arc::new(Dispatcher {
+            hooks: config.webhooks.clone(),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            log: Mutex::new(VecDeque::with_capacity(LOG_SIZE)),
+        });
+        if !dispatcher.hooks.is_empty() {
+            let (_, rx) = events.subscribe(None);
+            tokio::spawn(dispatcher.clone().run(rx));
+        }
+        dispatcher
+    }
+
+    /// Most recent deliveries first
+    pub fn deliveries(&self) -> Vec<Delivery> {
+        self.log.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    /// Subscribed by the caller, so nothing published after `start` returns gets missed
+    async fn run(self: Arc<Self>, mut rx: broadcast::Receiver<Envelope>) {
+        loop {
+            match rx.recv().await {
+                Ok(envelope) => {
+                    for hook in self.hooks.iter().filter(|h| h.wants(&envelope)) {
+                        tokio::spawn(self.clone().deliver(hook.clone(), envelope.clone()));
+                    }
+                },
+                Err(RecvError::Lagged(n)) => println!("Webhooks fell behind, skipped {n} events"),
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+
+    async fn deliver(self: Arc<Self>, hook: WebhookConfig, envelope: Envelope) {
+        let body = if let Ok(b) = serde_json::to_string(&envelope) { b } else { return };
+        let signature = hook.secret.as_ref().map(|secret| {
+            let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+            format!("sha256={}", hex::encode(hmac::sign(&key, body.as_bytes()).as_ref()))
+        });
+
+        let mut delivery = Delivery {
+            event_id: envelope.id,
+            server: envelope.server.clone(),
+            event: envelope.event.event.kind().to_string(),
+            url: hook.url.clone(),
+            time: Utc::now(),
+            attempts: 0,
+            status: None,
+            success: false,
+            error: None,
+        };
+
+        let mut backoff = 1;
+        while delivery.attempts < ATTEMPTS {
+            delivery.attempts += 1;
+
+            let mut req = self.client.post(&hook.url)
+                .header("Content-Type", "application/json")
+                .header("X-Mc-Docker-Event", delivery.event.as_str())
+                .header("X-Mc-Docker-Delivery", envelope.id.to_string())
+                .body(body.clone());
+            if let Some(sig) = &signature {
+                req = req.header("X-Mc-Docker-Signature", sig.as_str());
+            }
+
+            match req.send().await {
+                Ok(res) => {
+                    delivery.status = Some(res.status().as_u16());
+                    delivery.success = res.status().is_success();
+                    delivery.error = None;
+                    // Client errors won't go away by sending the same thing again
+                    if delivery.success || res.status().is_client_error() {
+                        break;
+                    }
+                },
+                Err(e) => delivery.error = Some(e.to_string()),
+            }
+
+            if delivery.attempts < ATTEMPTS {
+                sleep(Duration::from_secs(backoff)).await;
+                backoff *= 5;
+            }
+        }
+
+        if !delivery.success {
+            println!("Webhook to {} failed after {} attempts", hook.url, delivery.attempts);
+        }
+
+        let mut log = self.log.lock().unwrap();
+        if log.len() == LOG_SIZE {
+            log.pop_front();
+        }
+        log.push_back(delivery);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use hyper::body::Bytes;
+    use warp::{Filter, http::{HeaderMap, StatusCode}};
+    use crate::bus::EventBus;
+    use crate::events::{Lifecycle, LogEvent};
+    use super::*;
+
+    struct StandIn {
+        addr: SocketAddr,
+        received: Arc<Mutex<Vec<(HeaderMap, Bytes)>>>,
+    }
+
+    /// A local endpoint that answers with `statuses` in order, then 200 for anything after that
+    fn stand_in(statuses: Vec<u16>) -> StandIn {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let statuses = Arc::new(Mutex::new(VecDeque::from(statuses)));
+        let log = received.clone();
+        let route = warp::post()
+            .and(warp::header::headers_cloned())
+            .and(warp::body::bytes())
+            .map(move |headers, body| {
+                log.lock().unwrap().push((headers, body));
+                let status = statuses.lock().unwrap().pop_front().unwrap_or(200);
+                warp::reply::with_status("", StatusCode::from_u16(status).unwrap())
+            });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        StandIn { addr, received }
+    }
+
+    fn dispatcher(hooks: Vec<WebhookConfig>) -> Arc<Dispatcher> {
+        Arc::new(Dispatcher { hooks, client: reqwest::Client::new(), log: Mutex::new(VecDeque::new()) })
+    }
+
+    fn hook(addr: SocketAddr, secret: Option<&str>) -> WebhookConfig {
+        WebhookConfig {
+            url: format!("http://{addr}/hook"),
+            events: Vec::new(),
+            servers: Vec::new(),
+            secret: secret.map(|s| s.to_string()),
+        }
+    }
+
+    fn join(id: u64, server: &str) -> Envelope {
+        Envelope { id, server: server.to_string(), event: LogEvent::now(Event::Join { player: "Steve".to_string() }) }
+    }
+
+    fn header<'a>(headers: &'a HeaderMap, name: &str) -> &'a str {
+        headers.get(name).and_then(|h| h.to_str().ok()).unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn signs_the_body() {
+        let stand_in = stand_in(vec![]);
+        let dispatcher = dispatcher(vec![]);
+        dispatcher.clone().deliver(hook(stand_in.addr, Some("s3cret")), join(7, "survival")).await;
+
+        let received = stand_in.received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let (headers, body) = &received[0];
+        assert_eq!(header(headers, "x-mc-docker-event"), "join");
+        assert_eq!(header(headers, "x-mc-docker-delivery"), "7");
+        assert_eq!(header(headers, "content-type"), "application/json");
+
+        let signature = header(headers, "x-mc-docker-signature").strip_prefix("sha256=").unwrap();
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"s3cret");
+        assert!(hmac::verify(&key, body, &hex::decode(signature).unwrap()).is_ok());
+        let wrong = hmac::Key::new(hmac::HMAC_SHA256, b"not it");
+        assert!(hmac::verify(&wrong, body, &hex::decode(signature).unwrap()).is_err());
+
+        let json: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!((json["id"].as_u64(), json["server"].as_str(), json["type"].as_str()), (Some(7), Some("survival"), Some("join")));
+    }
+
+    #[tokio::test]
+    async fn unsigned_without_a_secret() {
+        let stand_in = stand_in(vec![]);
+        dispatcher(vec![]).deliver(hook(stand_in.addr, None), join(1, "survival")).await;
+        assert!(stand_in.received.lock().unwrap()[0].0.get("x-mc-docker-signature").is_none());
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let stand_in = stand_in(vec![503]);
+        let dispatcher = dispatcher(vec![]);
+        dispatcher.clone().deliver(hook(stand_in.addr, None), join(3, "survival")).await;
+
+        assert_eq!(stand_in.received.lock().unwrap().len(), 2);
+        let log = dispatcher.deliveries();
+        assert_eq!(log.len(), 1);
+        assert_eq!((log[0].attempts, log[0].status, log[0].success), (2, Some(200), true));
+        assert_eq!((log[0].event_id, log[0].event.as_str(), log[0].server.as_str()), (3, "join", "survival"));
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_client_errors() {
+        let stand_in = stand_in(vec![404]);
+        let dispatcher = dispatcher(vec![]);
+        dispatcher.clone().deliver(hook(stand_in.addr, None), join(4, "survival")).await;
+
+        assert_eq!(stand_in.received.lock().unwrap().len(), 1);
+        let log = dispatcher.deliveries();
+        assert_eq!((log[0].attempts, log[0].status, log[0].success), (1, Some(404), false));
+    }
+
+    #[tokio::test]
+    async fn delivers_from_the_bus() {
+        let stand_in = stand_in(vec![]);
+        let mut only = hook(stand_in.addr, None);
+        only.events = vec!["join".to_string(), "stopped".to_string()];
+        only.servers = vec!["survival".to_string()];
+
+        let events: Events = Arc::new(EventBus::new());
+        let dispatcher = dispatcher(vec![only]);
+        let (_, rx) = events.subscribe(None);
+        tokio::spawn(dispatcher.clone().run(rx));
+
+        events.publish("creative", LogEvent::now(Event::Join { player: "Alex".to_string() }));
+        events.publish("survival", LogEvent::now(Event::Leave { player: "Steve".to_string() }));
+        events.publish("survival", LogEvent::lifecycle(Lifecycle::Starting));
+        events.publish("survival", LogEvent::now(Event::Join { player: "Steve".to_string() }));
+        events.publish("survival", LogEvent::lifecycle(Lifecycle::Stopped));
+
+        for _ in 0..100 {
+            if dispatcher.deliveries().len() == 2 {
+                break;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        // Each delivery is its own task, so they can finish in either order
+        let mut log: Vec<(u64, String)> = dispatcher.deliveries().into_iter().map(|d| (d.event_id, d.event)).collect();
+        log.sort();
+        assert_eq!(log, [(4, "join".to_string()), (5, "lifecycle".to_string())]);
+        assert_eq!(stand_in.received.lock().unwrap().len(), 2);
+    }
+}
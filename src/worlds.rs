@@ -0,0 +1,422 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use futures::Stream;
+use hyper::body::Buf;
+use serde::{Deserialize, Serialize};
+use tokio_util::io::ReaderStream;
+use crate::Config;
+use crate::error::Error;
+use crate::files;
+use crate::properties::Properties;
+use crate::server::Server;
+use crate::watcher::StateTracker;
+
+// Bukkit based servers keep the other dimensions next to the world rather than inside it
+const DIMENSIONS: [&str; 3] = ["", "_nether", "_the_end"];
+const UPLOAD: &str = ".world-upload";
+const OLD: &str = ".world-old";
+
+#[derive(Serialize, Debug)]
+pub struct World {
+    pub name: String,
+    pub current: bool,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct WorldQuery {
+    pub world: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Reset {
+    pub seed: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Switch {
+    pub world: String,
+}
+
+/// World names end up as folder names and in the compose file
+fn check_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.len() > 64 || name.starts_with('.')
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') {
+        return Err(Error::from("Not a valid world name"));
+    }
+    Ok(())
+}
+
+pub(crate) fn dimensions(server: &Server, world: &str) -> Vec<String> {
+    DIMENSIONS.iter()
+        .map(|d| format!("{world}{d}"))
+        .filter(|d| Path::new(&format!("{}/{d}", server.path)).is_dir())
+        .collect()
+}
+
+/// Every folder in the server directory that holds a world
+pub fn list(server: &Server) -> Result<Vec<World>, Error> {
+    let current = server.level();
+    let dir = if let Ok(d) = fs::read_dir(&server.path) { d } else {
+        return Err(Error::from("Failed to read the server directory"));
+    };
+    let names: Vec<String> = dir
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().join("level.dat").is_file())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect();
+    let mut worlds: Vec<World> = names.iter()
+        // The other dimensions of a Bukkit world aren't worlds of their own, as long as the world
+        // they belong to is there
+        .filter(|n| !DIMENSIONS[1..].iter().any(|d| {
+            n.strip_suffix(d).map(|base| names.iter().any(|w| w == base)).unwrap_or(false)
+        }))
+        .map(|name| World { current: *name == current, name: name.clone() })
+        .collect();
+    if !worlds.iter().any(|w| w.current) {
+        // Not generated yet, it will be on the next start
+        worlds.push(World { name: current, current: true });
+    }
+    worlds.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(worlds)
+}
+
+/// Streams a world, with its other dimensions, as a tar.gz, saving it first when the server is up
+pub async fn download(server: &Server, world: Option<&str>, running: bool) -> Result<(String, ReaderStream<tokio::process::ChildStdout>), Error> {
+    let world = world.map(|w| w.to_string()).unwrap_or(server.level());
+    check_name(&world)?;
+    let dirs = dimensions(server, &world);
+    if dirs.is_empty() {
+        return Err(Error::from("World doesn't exist"));
+    }
+
+    // Nothing gets written to the world while it's being read
+    let live = running && world == server.level();
+    if live {
+        server.send_command(vec!["save-off".to_string()]).await?;
+        server.send_command(vec!["save-all".to_string(), "flush".to_string()]).await?;
+    }
+
+    let child = tokio::process::Command::new("tar")
+        .arg("-czf").arg("-")
+        .arg("-C").arg(&server.path)
+        .args(&dirs)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(c) => c,
+        Err(_) => {
+            if live { let _ = server.send_command(vec!["save-on".to_string()]).await; }
+            return Err(Error::from("Failed to run tar"));
+        },
+    };
+    let stdout = if let Some(s) = child.stdout.take() { s } else {
+        if live { let _ = server.send_command(vec!["save-on".to_string()]).await; }
+        return Err(Error::from("Failed to read the output of tar"));
+    };
+
+    let server = server.clone();
+    tokio::spawn(async move {
+        let _ = child.wait().await;
+        if live {
+            if let Err(e) = server.send_command(vec!["save-on".to_string()]).await {
+                println!("Failed to turn saving back on for {}: {:?}", server.name, e);
+            }
+        }
+    });
+    Ok((format!("{world}.tar.gz"), ReaderStream::new(stdout)))
+}
+
+/// The folder in an extracted archive that has level.dat in it, at most a couple of levels down
+fn find_level(dir: &Path, depth: u32) -> Option<PathBuf> {
+    if dir.join("level.dat").is_file() {
+        return Some(dir.to_path_buf());
+    }
+    if depth == 0 {
+        return None;
+    }
+    fs::read_dir(dir).ok()?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
+        .find_map(|e| find_level(&e.path(), depth - 1))
+}
+
+fn remove_world(server: &Server, world: &str) -> Result<(), Error> {
+    for dir in dimensions(server, world) {
+        if let Err(_) = fs::remove_dir_all(format!("{}/{dir}", server.path)) {
+            return Err(Error::from("Failed to delete the old world"));
+        }
+    }
+    Ok(())
+}
+
+/// Moves the folders in `moved` from `from` back into the server directory, as far as it can
+fn put_back(server: &Server, from: &str, moved: &[String]) {
+    for dir in moved {
+        if let Err(_) = fs::rename(format!("{from}/{dir}"), format!("{}/{dir}", server.path)) {
+            println!("Failed to put {dir} back in place on {}, it's still in {from}", server.name);
+        }
+    }
+}
+
+/// Replaces a world with an uploaded tar.gz or zip, the server has to be stopped
+pub async fn upload<S, B>(server: &Server, world: Option<&str>, body: S, states: &StateTracker) -> Result<String, Error>
+    where S: Stream<Item = Result<B, warp::Error>> + Unpin, B: Buf
+{
+    let world = world.map(|w| w.to_string()).unwrap_or(server.level());
+    check_name(&world)?;
+
+    let archive = format!("{}/{UPLOAD}", server.path);
+    let extracted = format!("{}/{UPLOAD}.d", server.path);
+    let _ = fs::remove_dir_all(&extracted);
+    files::upload(server, UPLOAD, body).await?;
+
+    // Big uploads take a while, so it's checked again right before the world is touched
+    let result = if states.inspect(server).await {
+        Err(Error::from("The server was started during the upload"))
+    } else {
+        replace(server, &world, &archive, &extracted).await
+    };
+    let _ = fs::remove_file(&archive);
+    let _ = fs::remove_dir_all(&extracted);
+    result.map(|_| world)
+}
+
+async fn replace(server: &Server, world: &str, archive: &str, extracted: &str) -> Result<(), Error> {
+    if let Err(_) = fs::create_dir_all(extracted) {
+        return Err(Error::from("Error creating a directory to extract into"));
+    }
+
+    let mut magic = [0u8; 2];
+    let zip = fs::File::open(archive).and_then(|mut f| std::io::Read::read_exact(&mut f, &mut magic)).is_ok() && &magic == b"PK";
+    let mut cmd = if zip {
+        let mut c = tokio::process::Command::new("unzip");
+        c.arg("-q").arg(archive).arg("-d").arg(extracted);
+        c
+    } else {
+        let mut c = tokio::process::Command::new("tar");
+        c.arg("-xzf").arg(archive).arg("-C").arg(extracted);
+        c
+    };
+    match cmd.output().await {
+        Ok(o) if o.status.success() => {},
+        _ => return Err(Error::from("Failed to extract the archive, it has to be a tar.gz or zip")),
+    }
+
+    let level = if let Some(l) = find_level(Path::new(extracted), 2) { l } else {
+        return Err(Error::from("There's no level.dat in the archive"));
+    };
+    let parent = level.parent().map(|p| p.to_path_buf()).unwrap_or(level.clone());
+    let uploaded = level.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+
+    // The current world is only moved aside until the new one is in place, so it can go back
+    // where it was if that fails halfway
+    let old = format!("{}/{OLD}", server.path);
+    let _ = fs::remove_dir_all(&old);
+    if let Err(_) = fs::create_dir_all(&old) {
+        return Err(Error::from("Error creating a directory for the old world"));
+    }
+    let mut moved = Vec::new();
+    for dir in dimensions(server, world) {
+        if let Err(_) = fs::rename(format!("{}/{dir}", server.path), format!("{old}/{dir}")) {
+            put_back(server, &old, &moved);
+            let _ = fs::remove_dir(&old);
+            return Err(Error::from("Failed to move the old world out of the way"));
+        }
+        moved.push(dir);
+    }
+
+    let mut placed = Vec::new();
+    for d in DIMENSIONS {
+        // The world itself, then Bukkit style dimensions that came along with it
+        let from = if d.is_empty() { level.clone() } else { parent.join(format!("{uploaded}{d}")) };
+        if d.is_empty() || from.is_dir() {
+            let to = format!("{}/{world}{d}", server.path);
+            if let Err(_) = fs::rename(&from, &to) {
+                for dir in placed {
+                    let _ = fs::remove_dir_all(dir);
+                }
+                put_back(server, &old, &moved);
+                let _ = fs::remove_dir(&old);
+                return Err(Error::from("Failed to move the new world into place"));
+            }
+            placed.push(to);
+        }
+    }
+
+    let _ = fs::remove_dir_all(&old);
+    Ok(())
+}
+
+/// Throws the current world away so a new one is generated on the next start, the server has
+/// to be stopped. A backup is taken first, its path is handed back
+pub async fn reset(server: &Server, reset: &Reset, config: &Config) -> Result<String, Error> {
+    let seed = reset.seed.as_ref().map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+    if seed.as_ref().map(|s| s.chars().any(|c| c.is_control())).unwrap_or(false) {
+        return Err(Error::from("Not a valid seed"));
+    }
+
+    let backup = server.backup(config).await?;
+    remove_world(server, &server.level())?;
+    server.set_seed(seed.clone())?;
+    // The image only writes level-seed when SEED is set, the old one would stick around otherwise
+    if let Ok(mut props) = Properties::read(server) {
+        props.set("level-seed", seed.as_deref().unwrap_or_default());
+        props.write(server)?;
+    }
+    server.compose_up(false).await?;
+    Ok(backup)
+}
+
+/// Makes the server load another world, which is generated on start when it doesn't exist yet
+pub async fn switch(server: &Server, switch: &Switch) -> Result<(), Error> {
+    check_name(&switch.world)?;
+    if DIMENSIONS[1..].iter().any(|d| switch.world.ends_with(d)) {
+        return Err(Error::from("That's a dimension of another world"));
+    }
+    server.set_level(&switch.world)?;
+    server.compose_up(false).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(name: &str, worlds: &[&str]) -> Server {
+        let path = std::env::temp_dir().join(format!("mc-docker-worlds-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        for w in worlds {
+            fs::create_dir_all(path.join(w)).unwrap();
+            fs::write(path.join(w).join("level.dat"), "").unwrap();
+        }
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "id": "",
+            "path": path.to_string_lossy(),
+            "port": 25565,
+        })).unwrap()
+    }
+
+    fn names(server: &Server) -> Vec<String> {
+        let names = list(server).unwrap().into_iter().map(|w| w.name).collect();
+        let _ = fs::remove_dir_all(&server.path);
+        names
+    }
+
+    #[test]
+    fn list_skips_dimensions() {
+        let s = server("dimensions", &["world", "world_nether", "world_the_end", "creative"]);
+        assert_eq!(names(&s), vec!["creative", "world"]);
+    }
+
+    #[test]
+    fn list_keeps_worlds_named_like_dimensions() {
+        let s = server("named", &["world", "my_nether", "the_end"]);
+        assert_eq!(names(&s), vec!["my_nether", "the_end", "world"]);
+    }
+
+    /// Packs `files` (path, contents) up the way someone would upload them
+    fn pack(server: &Server, name: &str, files: &[(&str, &str)], zip: bool) -> (String, String) {
+        let staging = Path::new(&server.path).join(format!("{name}.staging"));
+        for (file, contents) in files {
+            fs::create_dir_all(staging.join(file).parent().unwrap()).unwrap();
+            fs::write(staging.join(file), contents).unwrap();
+        }
+        let archive = format!("{}/{name}", server.path);
+        let status = if zip {
+            std::process::Command::new("zip").args(["-q", "-r", &archive, "."]).current_dir(&staging).status()
+        } else {
+            std::process::Command::new("tar").args(["-czf", &archive, "-C"]).arg(&staging).arg(".").status()
+        };
+        assert!(status.unwrap().success());
+        fs::remove_dir_all(&staging).unwrap();
+        (archive, format!("{}/{name}.d", server.path))
+    }
+
+    fn read(server: &Server, file: &str) -> Option<String> {
+        fs::read_to_string(format!("{}/{file}", server.path)).ok()
+    }
+
+    #[tokio::test]
+    async fn replace_brings_dimensions_along() {
+        let s = server("nested", &["world", "world_nether", "world_the_end"]);
+        fs::write(format!("{}/world/level.dat", s.path), "old").unwrap();
+        let (archive, extracted) = pack(&s, "upload", &[
+            ("export/MyWorld/level.dat", "new"),
+            ("export/MyWorld/region/r.0.0.mca", "new"),
+            ("export/MyWorld_nether/DIM-1/region/r.0.0.mca", "new"),
+        ], false);
+
+        replace(&s, "world", &archive, &extracted).await.unwrap();
+        assert_eq!(read(&s, "world/level.dat").as_deref(), Some("new"));
+        assert_eq!(read(&s, "world_nether/DIM-1/region/r.0.0.mca").as_deref(), Some("new"));
+        // The old world had an end, the new one doesn't
+        assert!(!Path::new(&format!("{}/world_the_end", s.path)).exists());
+        assert!(!Path::new(&format!("{}/{OLD}", s.path)).exists());
+        let _ = fs::remove_dir_all(&s.path);
+    }
+
+    #[tokio::test]
+    async fn replace_from_a_zip() {
+        let s = server("zip", &["world"]);
+        let (archive, extracted) = pack(&s, "upload.zip", &[("level.dat", "new"), ("region/r.0.0.mca", "new")], true);
+        replace(&s, "world", &archive, &extracted).await.unwrap();
+        assert_eq!(read(&s, "world/level.dat").as_deref(), Some("new"));
+        assert_eq!(read(&s, "world/region/r.0.0.mca").as_deref(), Some("new"));
+        let _ = fs::remove_dir_all(&s.path);
+    }
+
+    #[tokio::test]
+    async fn replace_keeps_the_old_world_on_failure() {
+        let s = server("failure", &["world"]);
+        fs::write(format!("{}/world/level.dat", s.path), "old").unwrap();
+
+        let (archive, extracted) = pack(&s, "empty", &[("readme.txt", "no world here")], false);
+        assert!(replace(&s, "world", &archive, &extracted).await.is_err());
+        assert_eq!(read(&s, "world/level.dat").as_deref(), Some("old"));
+
+        // Something that isn't a folder is in the way of the new nether, so the move fails halfway
+        fs::write(format!("{}/world_nether", s.path), "in the way").unwrap();
+        let (archive, extracted) = pack(&s, "upload", &[
+            ("MyWorld/level.dat", "new"),
+            ("MyWorld_nether/DIM-1/level", "new"),
+        ], false);
+        assert!(replace(&s, "world", &archive, &extracted).await.is_err());
+        assert_eq!(read(&s, "world/level.dat").as_deref(), Some("old"));
+        assert_eq!(read(&s, "world_nether").as_deref(), Some("in the way"));
+        assert!(!Path::new(&format!("{}/{OLD}", s.path)).exists());
+        let _ = fs::remove_dir_all(&s.path);
+    }
+
+    #[tokio::test]
+    async fn reset_backs_up_first() {
+        let s = server("reset", &["world"]);
+        let config = |backups: String| -> Config {
+            serde_json::from_value(serde_json::json!({
+                "fb_id": "",
+                "ws_port": 0,
+                "path": s.path,
+                "backups": backups,
+            })).unwrap()
+        };
+
+        // Nowhere to put the backup, so the world stays
+        fs::write(format!("{}/not-a-dir", s.path), "").unwrap();
+        let broken = config(format!("{}/not-a-dir", s.path));
+        assert!(reset(&s, &Reset::default(), &broken).await.is_err());
+        assert!(Path::new(&format!("{}/world/level.dat", s.path)).is_file());
+
+        // There's no compose file here so it doesn't get further than deleting the world, but the
+        // backup has to be there by then
+        let backups = std::env::temp_dir().join(format!("mc-docker-worlds-{}-backups", std::process::id()));
+        let _ = reset(&s, &Reset::default(), &config(backups.to_string_lossy().to_string())).await;
+        assert!(!Path::new(&format!("{}/world", s.path)).exists());
+        let archive = fs::read_dir(backups.join("reset")).unwrap().next().unwrap().unwrap().path();
+        let listing = std::process::Command::new("tar").arg("-tzf").arg(&archive).output().unwrap();
+        assert!(String::from_utf8_lossy(&listing.stdout).lines().any(|l| l == "./world/level.dat"));
+        let _ = fs::remove_dir_all(&backups);
+        let _ = fs::remove_dir_all(&s.path);
+    }
+}